@@ -12,11 +12,13 @@ use futures::stream::{FuturesUnordered, StreamExt};
 use lazy_static::lazy_static;
 use regex::Regex;
 use reqwest::Client;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{fs, time::Duration};
 use tokio::{select, sync::Mutex};
 lazy_static! {
     static ref CONFIG: Config =
         toml::from_str(fs::read_to_string("./config.toml").unwrap().as_str()).unwrap();
+    static ref CLIENT: Client =
+        build_client(&CONFIG.http).expect("无法根据 [http] 配置构建 HTTP 客户端！");
 }
 fn determine_auth(bot: &BotInfo) -> BotAuthorization {
     fn parse_md5(md5_str: &str) -> [u8; 16] {
@@ -82,12 +84,21 @@ fn determine_protocol(protocol: &str) -> MiraiProtocol {
 
 #[tokio::main]
 async fn main() {
+    // 离线的子命令，不需要登录。
+    if let Some(cmd) = std::env::args().nth(1) {
+        match cmd.as_str() {
+            "migrate" => migrate_library(),
+            "import" => import_legacy(),
+            "repair" => {
+                let delete_orphans = std::env::args().any(|arg| arg == "--delete-orphans");
+                repair_library(delete_orphans).await
+            }
+            _ => eprintln!("未知的子命令：{}", cmd),
+        }
+        return;
+    }
     let (ql_tx, mut ql_rx) = futures::channel::mpsc::unbounded();
-    let (lq_tx, mut lq_rx) = futures::channel::mpsc::unbounded::<(
-        Group,
-        Member,
-        Mutex<HashMap<PathBuf, MessageChain>>,
-    )>();
+    let (lq_tx, mut lq_rx) = futures::channel::mpsc::unbounded::<Delivery>();
     let (ctrlc_tx, mut ctrlc_rx) = futures::channel::mpsc::unbounded();
     let ql_tx = Box::leak(Box::new(ql_tx));
     let rx = Box::leak(Box::new(Regex::new(&CONFIG.cmn_rx).unwrap()));
@@ -114,60 +125,83 @@ async fn main() {
         Box::new(|event: GroupMessageEvent| {
             let group = event.get_subject();
             let sender = event.get_sender();
-            if CONFIG.prem.groups.contains(&group.get_id())
-                && CONFIG.prem.members.contains(&sender.get_id())
-            {
-                let msg = event.get_message().to_content();
+            if !CONFIG.prem.groups.contains(&group.get_id()) {
+                return;
+            }
+            let msg = event.get_message().to_content();
+            if CONFIG.prem.admins.contains(&sender.get_id()) && handle_admin(&msg, &group) {
+                return;
+            }
+            if CONFIG.prem.members.contains(&sender.get_id()) {
+                if handle_original(&msg, &group, &sender) || handle_tile(&msg, &group, &sender) {
+                    return;
+                }
                 let caps = rx.captures(&msg);
                 if let Some(caps) = caps {
+                    let chat = Chat::Group(group, sender);
                     match rxcap(caps) {
-                        Ok((num, r18, tag, ai)) => {
-                            let req_data = build_req_data(num, r18, tag, ai, &group, &CONFIG);
-                            let _ = ql_tx.unbounded_send((group, sender, req_data));
+                        Ok(cmd) => {
+                            let req_data = build_req_data(&cmd, &chat, &CONFIG);
+                            let ticket = enqueue(&chat);
+                            let _ = ql_tx.unbounded_send((chat, cmd, req_data, ticket));
                         }
                         Err(err) => {
-                            handle_err(err, &group, &CONFIG);
+                            handle_err(err, &chat, &CONFIG);
                         }
                     }
                 }
             }
         });
     let listener_for_group_message_event = event_channel.subscribe_always(&on_group_message_event);
-    let send_image_task = async {
-        while let Some((
-            group,
-            _sender, //私发功能要用来着，但是懒得写了，又不是不能用。
-            msgs_m,
-        )) = lq_rx.next().await
-        {
-            for (filepath, msg) in &*msgs_m.lock().await {
-                if let Ok(_) = filepath.metadata() {
-                    let image = group.upload_image_from_file(filepath.to_str().unwrap());
-                    group.send_message(&msg.plus(image));
-                } else {
-                    let bad_msg = PlainText::from(CONFIG.err_msg.bad_dld.clone());
-                    group.send_message(&msg.plus(bad_msg));
+    let on_friend_message_event: Box<dyn Fn(FriendMessageEvent)> =
+        Box::new(|event: FriendMessageEvent| {
+            let friend = event.get_subject();
+            if !friend_allowed(&friend, &CONFIG.friend) {
+                return;
+            }
+            let msg = event.get_message().to_content();
+            if let Some(caps) = rx.captures(&msg) {
+                match rxcap(caps) {
+                    Ok(cmd) => {
+                        if !friend_may_request(&cmd, &friend, &CONFIG.friend) {
+                            return;
+                        }
+                        let chat = Chat::Friend(friend);
+                        let req_data = build_req_data(&cmd, &chat, &CONFIG);
+                        let ticket = enqueue(&chat);
+                        let _ = ql_tx.unbounded_send((chat, cmd, req_data, ticket));
+                    }
+                    Err(err) => {
+                        handle_err(err, &Chat::Friend(friend), &CONFIG);
+                    }
                 }
             }
-            // 发送完毕。
-            group.send_string(&CONFIG.tip_msg.tip_end);
+        });
+    let listener_for_friend_message_event =
+        event_channel.subscribe_always(&on_friend_message_event);
+    // 各次指令的发送互不等待，下载慢的指令不会挡住后面的；同一次指令中的图片仍按顺序发送。
+    let send_image_task = async {
+        let mut deliveries = FuturesUnordered::new();
+        loop {
+            select! {
+                delivery = lq_rx.next() => match delivery {
+                    Some(delivery) => deliveries.push(send_delivery(delivery)),
+                    None => break,
+                },
+                Some(_) = deliveries.next(), if !deliveries.is_empty() => {}
+            }
         }
+        while deliveries.next().await.is_some() {}
     };
     let tasks = Mutex::new(FuturesUnordered::new());
     let download_task = async {
-        let client: Client = Client::new();
-        while let Some((group, member, req_data)) = ql_rx.next().await {
+        while let Some((chat, cmd, req_data, ticket)) = ql_rx.next().await {
             println!("{:?}", req_data);
-            use trauma::downloader::DownloaderBuilder;
-            let mut pic_dir = std::env::current_dir().unwrap();
-            pic_dir.push("pictures");
-            let downloader = DownloaderBuilder::new().directory(pic_dir).build();
-            let send_post = client.post(&CONFIG.api_url).json(&req_data).send();
             let lq_tx = lq_tx.clone();
             // task 干的事情：
             //      发送 post 请求。
             //      获取响应数据然后异步地下载图片和构造不包含图片的 MessageChain.
-            let task = task(lq_tx, downloader, group, member, req_data, send_post.await);
+            let task = task(lq_tx, chat, cmd, req_data, ticket);
             let tasks = tasks.lock().await;
             tasks.push(task);
         }
@@ -178,6 +212,32 @@ async fn main() {
             if let Some(_) = tasks.next().await {}
         }
     };
+    // 定期按容量和期限淘汰缓存的图片。
+    let evict_task = async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(CONFIG.cache.evict_interval.max(1)));
+        loop {
+            interval.tick().await;
+            evict(&CONFIG.cache);
+        }
+    };
+    // 空闲时补充预取池。
+    let prefetch_task = async {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(CONFIG.prefetch.interval.max(1)));
+        loop {
+            interval.tick().await;
+            refill_pools(&CONFIG.prefetch).await;
+        }
+    };
+    // 撤回到期的消息，启动时先处理停机期间到期的。
+    let recall_task = async {
+        let mut interval = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            recall_due();
+        }
+    };
     let ctrlc_task = async {
         while let Some(_) = ctrlc_rx.next().await {
             break;
@@ -191,8 +251,12 @@ async fn main() {
         _ = send_image_task =>{},
         _ = download_task => {},
         _ = forward_task => {},
+        _ = evict_task => {},
+        _ = prefetch_task => {},
+        _ = recall_task => {},
         _ = ctrlc_task => {}
     }
     listener_for_group_message_event.complete();
+    listener_for_friend_message_event.complete();
     println!("complete!");
 }
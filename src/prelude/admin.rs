@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use strfmt::strfmt;
+
+use crate::{prelude::*, CONFIG};
+
+// 处理管理员指令，不是管理员指令时返回 false.
+//      {prefix}缓存            查看缓存统计。
+//      {prefix}下载            查看正在进行的下载。
+//      {prefix}固定 <pid>      固定图片，使其不会被淘汰。
+//      {prefix}取消固定 <pid>  取消固定。
+//      {prefix}查询 <pid>[_p<页码>] 或 {prefix}查询 <标签>...  在图库中查询。
+//      {prefix}退路 [小时]     查看最近（默认 24 小时）发送失败时用到的退路。
+pub(crate) fn handle_admin(msg: &str, group: &Group) -> bool {
+    let Some(cmd) = msg.trim().strip_prefix(&CONFIG.admin.prefix) else {
+        return false;
+    };
+    let mut args = cmd.split_whitespace();
+    match args.next() {
+        Some("缓存") => {
+            let stats = cache_stats();
+            let mut tmp = HashMap::new();
+            tmp.insert("pics".to_string(), stats.pics.to_string());
+            tmp.insert("files".to_string(), stats.files.to_string());
+            tmp.insert("pinned".to_string(), stats.pinned.to_string());
+            tmp.insert(
+                "mib".to_string(),
+                format!("{:.1}", stats.bytes as f64 / 1024.0 / 1024.0),
+            );
+            group.send_string(&strfmt(&CONFIG.admin.tip_stat, &tmp).unwrap());
+        }
+        Some("下载") => {
+            let progress = DOWNLOADER.progress();
+            let mut tmp = HashMap::new();
+            tmp.insert("n".to_string(), progress.len().to_string());
+            let mut msg = strfmt(&CONFIG.admin.tip_dl, &tmp).unwrap();
+            for (path, done, total) in progress {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let total = total.map_or("?".to_string(), |total| (total / 1024).to_string());
+                msg.push_str(&format!("\n{}: {}/{} KiB", name, done / 1024, total));
+            }
+            group.send_string(&msg);
+        }
+        Some(op @ ("固定" | "取消固定")) => {
+            let Some(pid) = args.next().and_then(|pid| pid.parse::<i64>().ok()) else {
+                return false;
+            };
+            let pinned = op == "固定";
+            let n = set_pinned(pid, pinned);
+            let mut tmp = HashMap::new();
+            tmp.insert("pid".to_string(), pid.to_string());
+            tmp.insert("n".to_string(), n.to_string());
+            let tip = if n == 0 {
+                &CONFIG.admin.bad_pin
+            } else if pinned {
+                &CONFIG.admin.tip_pin
+            } else {
+                &CONFIG.admin.tip_unpin
+            };
+            group.send_string(&strfmt(tip, &tmp).unwrap());
+        }
+        Some("查询") => {
+            let args = args.map(str::to_string).collect::<Vec<_>>();
+            // 第一个参数是 pid（可以带页码）时按 pid 查询，否则按标签查询。
+            let pic = args.first().and_then(|arg| {
+                let (pid, p) = arg.split_once("_p").unwrap_or((arg, "0"));
+                Some((pid.parse().ok()?, p.parse().ok()?))
+            });
+            let query = match pic {
+                Some((pid, p)) => load_pic(pid, p).map(|pic| pic.into_iter().collect::<Vec<_>>()),
+                None => find_pics(None, &args, 10),
+            };
+            let pics = match query {
+                Ok(pics) => pics,
+                Err(err) => {
+                    eprintln!("查询图库失败：{}", err);
+                    Vec::new()
+                }
+            };
+            let mut tmp = HashMap::new();
+            tmp.insert("n".to_string(), pics.len().to_string());
+            let mut msg = strfmt(&CONFIG.admin.tip_query, &tmp).unwrap();
+            for pic in pics {
+                msg.push_str(&format!(
+                    "\n{}_p{} {} - {} {:?}",
+                    pic.pid, pic.p, pic.title, pic.author, pic.tags
+                ));
+            }
+            group.send_string(&msg);
+        }
+        Some("退路") => {
+            let hours = args
+                .next()
+                .and_then(|h| h.parse::<i64>().ok())
+                .unwrap_or(24);
+            let since = now() - hours * 3600;
+            let counts = fallback_counts(since).unwrap_or_else(|err| {
+                eprintln!("无法读取退路记录：{}", err);
+                Vec::new()
+            });
+            let mut tmp = HashMap::new();
+            tmp.insert("hours".to_string(), hours.to_string());
+            let mut msg = strfmt(&CONFIG.admin.tip_fallback, &tmp).unwrap();
+            for (step, n) in counts {
+                msg.push_str(&format!("\n{}: {}", step, n));
+            }
+            group.send_string(&msg);
+        }
+        _ => return false,
+    }
+    true
+}
@@ -0,0 +1,411 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use url::Url;
+
+use super::{
+    delete_cache_entries, download_from_mirrors, forget_pic, load_cache_index, now, quarantine,
+    record_download, save_cache_entries,
+    structs::{CacheConfig, CacheEntry, CacheStats},
+    validate_image,
+};
+use crate::{CLIENT, CONFIG};
+
+lazy_static! {
+    // 缓存键 -> 缓存记录，保存在数据库中，这里是内存中的副本。
+    static ref INDEX: Mutex<HashMap<String, CacheEntry>> = Mutex::new(
+        load_cache_index().expect("无法读取缓存索引！")
+    );
+    // 每个缓存键一把锁，同一张图片的并发请求共用一次下载。
+    static ref LOCKS: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(HashMap::new());
+}
+
+// 只把改动过的记录写入数据库。
+fn save_entries(entries: &[(&str, &CacheEntry)]) {
+    if let Err(err) = save_cache_entries(entries) {
+        eprintln!("无法保存缓存索引：{}", err);
+    }
+}
+
+fn delete_entries(keys: &[String]) {
+    if let Err(err) = delete_cache_entries(keys) {
+        eprintln!("无法保存缓存索引：{}", err);
+    }
+}
+
+pub(crate) fn cache_key(pid: i64, p: i64, size: &str) -> String {
+    format!("{}_p{}_{}", pid, p, size)
+}
+
+// 确保缓存中有这张图片，返回下载时使用的镜像。
+// 缓存的文件在有效期内直接使用，过期后带上 ETag/Last-Modified 重新验证。
+// 没有通过校验的文件会被隔离，然后换一个镜像重新下载。
+pub(crate) async fn fetch_cached(
+    pid: i64,
+    p: i64,
+    size: &str,
+    dims: (i64, i64),
+    url: &Url,
+    path: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let key = cache_key(pid, p, size);
+    let lock = LOCKS
+        .lock()
+        .unwrap()
+        .entry(key.clone())
+        .or_default()
+        .clone();
+    let result = {
+        let _guard = lock.lock().await;
+        fetch_validated(&key, (pid, p, size), dims, url, path).await
+    };
+    let mut locks = LOCKS.lock().unwrap();
+    // 除了这里和表中的之外没有别的引用，说明没有人在等待。
+    if Arc::strong_count(&lock) == 2 {
+        locks.remove(&key);
+    }
+    result
+}
+
+async fn fetch_validated(
+    key: &str,
+    (pid, p, size): (i64, i64, &str),
+    dims: (i64, i64),
+    url: &Url,
+    path: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let mut exclude = Vec::new();
+    loop {
+        let mirror = fetch_locked(key, (pid, p, size), url, path, &exclude).await?;
+        let validated = INDEX
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|entry| entry.validated);
+        if validated {
+            return Ok(mirror);
+        }
+        match validate_image(path, size, dims, &CONFIG.validate).await {
+            Ok(()) => {
+                let mut index = INDEX.lock().unwrap();
+                if let Some(entry) = index.get_mut(key) {
+                    entry.validated = true;
+                    save_entries(&[(key, entry)]);
+                }
+                return Ok(mirror);
+            }
+            Err(reason) => {
+                eprintln!("{} 没有通过校验：{}", path.display(), reason);
+                quarantine(path);
+                INDEX.lock().unwrap().remove(key);
+                delete_entries(&[key.to_string()]);
+                exclude.push(mirror);
+            }
+        }
+    }
+}
+
+async fn fetch_locked(
+    key: &str,
+    (pid, p, size): (i64, i64, &str),
+    url: &Url,
+    path: &Path,
+    exclude: &[String],
+) -> Result<String, Box<dyn Error>> {
+    let cached = INDEX
+        .lock()
+        .unwrap()
+        .get(key)
+        .cloned()
+        .filter(|entry| fs::metadata(path).is_ok_and(|meta| meta.len() == entry.size));
+    if let Some(entry) = &cached
+        && (now() as u64).saturating_sub(entry.checked_at) < CONFIG.cache.revalidate_secs
+    {
+        touch(key);
+        return Ok(entry.mirror.clone());
+    }
+    let validators = cached
+        .as_ref()
+        .map(|entry| (entry.etag.as_str(), entry.last_modified.as_str()));
+    let (mirror, downloaded) =
+        download_from_mirrors(&CLIENT, url, path, &CONFIG.mirror, validators, exclude).await?;
+    let entry = match (downloaded, cached) {
+        (Some(downloaded), _) => {
+            if let Err(err) = record_download(pid, p, size, path, &mirror, downloaded.size) {
+                eprintln!("无法记录 {} 的下载：{}", key, err);
+            }
+            CacheEntry {
+                path: path.to_path_buf(),
+                mirror,
+                sha256: downloaded.sha256,
+                size: downloaded.size,
+                etag: downloaded.etag,
+                last_modified: downloaded.last_modified,
+                checked_at: now() as u64,
+                last_access: 0,
+                hits: 0,
+                pinned: false,
+                validated: false,
+            }
+        }
+        // 304, 缓存的文件仍然有效。
+        (None, Some(mut entry)) => {
+            entry.mirror = mirror;
+            entry.checked_at = now() as u64;
+            entry
+        }
+        (None, None) => return Err("服务器返回了 304, 但本地没有缓存。".into()),
+    };
+    let mirror = entry.mirror.clone();
+    INDEX.lock().unwrap().insert(key.to_string(), entry);
+    // touch 会把新的记录写入数据库。
+    touch(key);
+    Ok(mirror)
+}
+
+// 记录一次访问，供淘汰策略使用。
+fn touch(key: &str) {
+    let mut index = INDEX.lock().unwrap();
+    if let Some(entry) = index.get_mut(key) {
+        entry.last_access = now() as u64;
+        entry.hits += 1;
+        save_entries(&[(key, entry)]);
+    }
+}
+
+// 缓存键中的 pid 和页码部分，同一张图片的各个尺寸共用。
+fn pic_of(key: &str) -> &str {
+    key.rsplit_once('_').map_or(key, |(pic, _)| pic)
+}
+
+fn is_pinned(entry: &CacheEntry, pic: &str) -> bool {
+    entry.pinned
+        || CONFIG.cache.pinned.iter().any(|pid| {
+            pic.split_once('_')
+                .is_some_and(|(p, _)| p == pid.to_string())
+        })
+}
+
+// 固定或取消固定某个 pid 的所有图片，返回受影响的文件数。
+pub(crate) fn set_pinned(pid: i64, pinned: bool) -> usize {
+    let prefix = format!("{}_p", pid);
+    let mut index = INDEX.lock().unwrap();
+    let mut changed = Vec::new();
+    for (key, entry) in index.iter_mut().filter(|(key, _)| key.starts_with(&prefix)) {
+        entry.pinned = pinned;
+        changed.push((key.as_str(), &*entry));
+    }
+    save_entries(&changed);
+    changed.len()
+}
+
+pub(crate) fn cache_stats() -> CacheStats {
+    let index = INDEX.lock().unwrap();
+    let mut pics = HashSet::new();
+    let mut pinned = HashSet::new();
+    let mut stats = CacheStats::default();
+    for (key, entry) in index.iter() {
+        let pic = pic_of(key);
+        stats.files += 1;
+        stats.bytes += disk_size(entry);
+        pics.insert(pic);
+        if is_pinned(entry, pic) {
+            pinned.insert(pic);
+        }
+    }
+    stats.pics = pics.len();
+    stats.pinned = pinned.len();
+    stats
+}
+
+// 删除一张图片的所有尺寸以及它的元数据。
+fn remove_pic(index: &mut HashMap<String, CacheEntry>, pic: &str) {
+    let keys = index
+        .keys()
+        .filter(|key| pic_of(key) == pic)
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in &keys {
+        if let Some(entry) = index.remove(key) {
+            let _ = fs::remove_file(&entry.path);
+            remove_variants(&entry.path);
+        }
+    }
+    delete_entries(&keys);
+    if let Some((pid, p)) = pic.split_once("_p")
+        && let (Ok(pid), Ok(p)) = (pid.parse(), p.parse())
+        && let Err(err) = forget_pic(pid, p)
+    {
+        eprintln!("无法删除 {} 的元数据：{}", pic, err);
+    }
+}
+
+// 删除一张图片的所有尺寸、变换结果和元数据。
+pub(crate) fn forget_cached(pid: i64, p: i64) {
+    let mut index = INDEX.lock().unwrap();
+    remove_pic(&mut index, &format!("{}_p{}", pid, p));
+}
+
+// 按磁盘上实际存在的文件（缓存键、路径、大小）重建缓存索引，返回新增和丢弃的记录数。
+// 新增的记录没有 ETag 等信息，下次使用时会重新下载验证。
+pub(crate) fn reindex_cached(files: &[(String, PathBuf, u64)]) -> (usize, usize) {
+    let mut index = INDEX.lock().unwrap();
+    let stale = index
+        .iter()
+        .filter(|(key, entry)| {
+            !files
+                .iter()
+                .any(|(k, path, size)| k == *key && *path == entry.path && *size == entry.size)
+        })
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    for key in &stale {
+        index.remove(key);
+    }
+    delete_entries(&stale);
+    let mut added = Vec::new();
+    for (key, path, size) in files {
+        if index.contains_key(key) {
+            continue;
+        }
+        index.insert(
+            key.clone(),
+            CacheEntry {
+                path: path.clone(),
+                mirror: String::new(),
+                sha256: String::new(),
+                size: *size,
+                etag: String::new(),
+                last_modified: String::new(),
+                checked_at: 0,
+                last_access: now() as u64,
+                hits: 0,
+                pinned: false,
+                validated: true,
+            },
+        );
+        added.push(key.as_str());
+    }
+    let entries = added
+        .iter()
+        .filter_map(|&key| Some((key, index.get(key)?)))
+        .collect::<Vec<_>>();
+    save_entries(&entries);
+    (added.len(), stale.len())
+}
+
+// 迁移存储布局后更新缓存记录中的路径。
+pub(crate) fn relocate_cached(from: &Path, to: &Path) {
+    let mut index = INDEX.lock().unwrap();
+    let mut changed = Vec::new();
+    for (key, entry) in index.iter_mut().filter(|(_, entry)| entry.path == from) {
+        entry.path = to.to_path_buf();
+        changed.push((key.as_str(), &*entry));
+    }
+    save_entries(&changed);
+}
+
+// 变换后的结果（水印、变换、预览等）及其大小，它们与原图放在一起，文件名以原图的文件名（不含扩展名）开头。
+fn variants(path: &Path) -> Vec<(PathBuf, u64)> {
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| (entry.path(), entry.metadata().map_or(0, |meta| meta.len())))
+        .collect()
+}
+
+fn remove_variants(path: &Path) {
+    for (variant, _) in variants(path) {
+        let _ = fs::remove_file(variant);
+    }
+}
+
+// 一张图片的某个尺寸连同变换结果占用的空间。
+fn disk_size(entry: &CacheEntry) -> u64 {
+    entry.size
+        + variants(&entry.path)
+            .iter()
+            .map(|(_, size)| size)
+            .sum::<u64>()
+}
+
+// 刚取出的图片可能还在上传，这段时间（秒）内访问过的图片不淘汰。
+const IN_USE_SECS: u64 = 600;
+
+// 按配置的容量和期限淘汰缓存，固定的图片不会被淘汰。
+pub(crate) fn evict(config: &CacheConfig) {
+    // 正在下载或验证的图片也不淘汰。
+    let locked = LOCKS
+        .lock()
+        .unwrap()
+        .keys()
+        .map(|key| pic_of(key).to_string())
+        .collect::<HashSet<_>>();
+    let mut index = INDEX.lock().unwrap();
+    let in_use = (now() as u64).saturating_sub(IN_USE_SECS);
+    // pid 和页码 -> (总大小, 最近访问时间, 访问次数, 是否固定或正在使用)。
+    let mut pics: HashMap<String, (u64, u64, u64, bool)> = HashMap::new();
+    for (key, entry) in index.iter() {
+        let pic = pic_of(key);
+        let stat = pics.entry(pic.to_string()).or_default();
+        stat.0 += disk_size(entry);
+        stat.1 = stat.1.max(entry.last_access.max(entry.checked_at));
+        stat.2 += entry.hits;
+        stat.3 |= is_pinned(entry, pic) || locked.contains(pic);
+    }
+    for stat in pics.values_mut() {
+        stat.3 |= stat.1 >= in_use;
+    }
+    let mut victims = Vec::new();
+    if config.max_age_days > 0 {
+        let deadline = (now() as u64).saturating_sub(config.max_age_days * 24 * 3600);
+        victims.extend(
+            pics.iter()
+                .filter(|(_, (_, last_access, _, pinned))| !pinned && *last_access < deadline)
+                .map(|(pic, _)| pic.clone()),
+        );
+        for pic in &victims {
+            pics.remove(pic);
+        }
+    }
+    let mut total = pics.values().map(|(size, _, _, _)| size).sum::<u64>();
+    if config.max_bytes > 0 && total > config.max_bytes {
+        let mut candidates = pics
+            .into_iter()
+            .filter(|(_, (_, _, _, pinned))| !pinned)
+            .collect::<Vec<_>>();
+        if config.policy == "lfu" {
+            candidates.sort_by_key(|(_, (_, last_access, hits, _))| (*hits, *last_access));
+        } else {
+            candidates.sort_by_key(|(_, (_, last_access, _, _))| *last_access);
+        }
+        for (pic, (size, _, _, _)) in candidates {
+            if total <= config.max_bytes {
+                break;
+            }
+            total -= size;
+            victims.push(pic);
+        }
+    }
+    if victims.is_empty() {
+        return;
+    }
+    for pic in &victims {
+        remove_pic(&mut index, pic);
+    }
+    println!("淘汰了 {} 张缓存的图片。", victims.len());
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ab_glyph::{Font, PxScale, ScaleFont};
+use image::{imageops::FilterType, ImageReader, Rgb, RgbImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut},
+    rect::Rect,
+};
+use strfmt::strfmt;
+
+use super::{
+    collage_tile, load_font, make_preview, recall_delay, recall_later, register_collage,
+    register_preview,
+    structs::{CollageConfig, PicMsg},
+};
+use crate::{prelude::*, CONFIG};
+
+fn collage_path() -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let mut path = std::env::current_dir().unwrap();
+    path.push("pictures");
+    path.push("collages");
+    path.push(format!("{}.jpg", secs));
+    path
+}
+
+// 在格子底部画一条深色的底，写上说明。
+fn draw_caption(canvas: &mut RgbImage, x: u32, y: u32, caption: &str, config: &CollageConfig) {
+    let font = load_font(&config.font);
+    let scale = PxScale::from(config.font_size);
+    let height = font.as_scaled(scale).height().ceil() as u32 + 4;
+    let top = y + config.tile - height.min(config.tile);
+    draw_filled_rect_mut(
+        canvas,
+        Rect::at(x as i32, top as i32).of_size(config.tile, height),
+        Rgb([0, 0, 0]),
+    );
+    draw_text_mut(
+        canvas,
+        Rgb([255, 255, 255]),
+        x as i32 + 4,
+        top as i32 + 2,
+        scale,
+        &*font,
+        caption,
+    );
+}
+
+// 把图片按顺序排成网格，每张缩放后居中放在边长为 tile 的格子里。
+fn compose(tiles: &[(PathBuf, String)], config: &CollageConfig) -> Result<RgbImage, String> {
+    let n = tiles.len() as u32;
+    let columns = if config.columns > 0 {
+        config.columns.min(n)
+    } else {
+        (n as f64).sqrt().ceil() as u32
+    };
+    let rows = n.div_ceil(columns);
+    let tile = config.tile;
+    let mut canvas = RgbImage::from_pixel(columns * tile, rows * tile, Rgb([32, 32, 32]));
+    for (i, (path, caption)) in tiles.iter().enumerate() {
+        let (x, y) = ((i as u32 % columns) * tile, (i as u32 / columns) * tile);
+        let image = ImageReader::open(path)
+            .map_err(|err| err.to_string())?
+            .with_guessed_format()
+            .map_err(|err| err.to_string())?
+            .decode()
+            .map_err(|err| format!("无法解码 {}：{}", path.display(), err))?;
+        let image = image.resize(tile, tile, FilterType::Triangle).to_rgb8();
+        let (w, h) = image.dimensions();
+        image::imageops::overlay(
+            &mut canvas,
+            &image,
+            (x + (tile - w) / 2) as i64,
+            (y + (tile - h) / 2) as i64,
+        );
+        if config.captions {
+            draw_caption(&mut canvas, x, y, caption, config);
+        }
+    }
+    Ok(canvas)
+}
+
+// 生成拼图，敏感图片使用预览图。返回拼图的路径，以及每一格对应的图片。
+pub(crate) async fn make_collage<'a>(
+    pics: &'a [(PathBuf, PicMsg)],
+    config: &CollageConfig,
+) -> Option<(PathBuf, Vec<&'a (PathBuf, PicMsg)>)> {
+    let mut used = Vec::new();
+    let mut tiles = Vec::new();
+    for pic in pics {
+        let (path, pic_msg) = pic;
+        if fs::metadata(path).is_err() {
+            continue;
+        }
+        let path = if CONFIG.preview.enabled && pic_msg.sensitive {
+            make_preview(path, &CONFIG.preview).await?
+        } else {
+            path.clone()
+        };
+        used.push(pic);
+        tiles.push((path, format!("#{} {}", used.len(), pic_msg.pid)));
+    }
+    if tiles.is_empty() {
+        return None;
+    }
+    let path = collage_path();
+    let target = path.clone();
+    let config = config.clone();
+    let composed = tokio::task::spawn_blocking(move || {
+        let canvas = compose(&tiles, &config)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        canvas.save(&target).map_err(|err| err.to_string())
+    })
+    .await;
+    match composed {
+        Ok(Ok(())) => Some((path, used)),
+        Ok(Err(err)) => {
+            eprintln!("无法生成拼图：{}", err);
+            None
+        }
+        Err(err) => {
+            eprintln!("无法生成拼图：{}", err);
+            None
+        }
+    }
+}
+
+// 把图片拼成一张发到群里，登记每一格对应的原图。返回拼进去的图片的 pid, 失败时返回 None.
+pub(crate) async fn send_collage(
+    group: &Group,
+    member: &Member,
+    pics: &[(PathBuf, PicMsg)],
+    config: &CollageConfig,
+) -> Option<Vec<i64>> {
+    let (path, used) = make_collage(pics, config).await?;
+    let image = try_upload(group, &path);
+    let _ = fs::remove_file(&path);
+    let image = image?;
+    let tiles = used
+        .iter()
+        .map(|(path, pic_msg)| (pic_msg.pid, pic_msg.p, pic_msg.sensitive, path.as_path()))
+        .collect::<Vec<_>>();
+    let id = match register_collage(group.get_id(), &tiles) {
+        Ok(id) => id,
+        Err(err) => {
+            eprintln!("无法登记拼图：{}", err);
+            return None;
+        }
+    };
+    let mut tmp = HashMap::new();
+    tmp.insert("id".to_string(), id.to_string());
+    tmp.insert("n".to_string(), used.len().to_string());
+    tmp.insert("cmd".to_string(), config.cmd.clone());
+    let tip = strfmt(&config.tip_collage, &tmp).unwrap();
+    let msg = At::new(member.get_id())
+        .plus(image)
+        .plus(PlainText::from(tip));
+    let receipt = try_send(group, &msg)?;
+    // 敏感图片在拼图中已是预览图，按普通图片的延迟撤回。
+    let delay = recall_delay(group.get_id(), false, &CONFIG.recall);
+    recall_later(group.get_id(), &receipt, delay);
+    Some(used.iter().map(|(_, pic_msg)| pic_msg.pid).collect())
+}
+
+// 处理“大图 [拼图编号] <序号>”指令，省略拼图编号时取本群最近的一张拼图。不是该指令（或没有开启拼图）时返回 false.
+pub(crate) fn handle_tile(msg: &str, group: &Group, member: &Member) -> bool {
+    if !CONFIG.collage.enabled {
+        return false;
+    }
+    let Some(args) = msg.trim().strip_prefix(&CONFIG.collage.cmd) else {
+        return false;
+    };
+    let args = args
+        .split_whitespace()
+        .map(|arg| arg.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>();
+    let (id, index) = match args.as_deref() {
+        Ok([index]) => (None, *index),
+        Ok([id, index]) => (Some(*id), *index),
+        _ => return false,
+    };
+    let mut tmp = HashMap::new();
+    tmp.insert("index".to_string(), index.to_string());
+    let tile = match collage_tile(group.get_id(), id, index) {
+        Ok(tile) => tile.filter(|(_, _, path)| fs::metadata(path).is_ok()),
+        Err(err) => {
+            eprintln!("无法查询拼图：{}", err);
+            None
+        }
+    };
+    let Some((pid, sensitive, path)) = tile else {
+        let bad_tile = strfmt(&CONFIG.collage.bad_tile, &tmp).unwrap();
+        try_send(group, &PlainText::from(bad_tile));
+        return true;
+    };
+    let at = At::new(member.get_id());
+    // 敏感图片不直接发原图，而是登记预览，让成员用获取原图的指令。
+    if CONFIG.preview.enabled && sensitive {
+        let id = register_preview(group.get_id(), pid, &path);
+        tmp.insert("id".to_string(), id.to_string());
+        tmp.insert("cmd".to_string(), CONFIG.preview.cmd.clone());
+        let tip = strfmt(&CONFIG.preview.tip_preview, &tmp).unwrap();
+        try_send(group, &at.plus(PlainText::from(tip)));
+        return true;
+    }
+    let sent = try_upload(group, &path).and_then(|image| try_send(group, &at.plus(image)));
+    if sent.is_none() {
+        try_send(group, &PlainText::from(CONFIG.err_msg.bad_dld.as_str()));
+    }
+    true
+}
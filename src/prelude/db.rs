@@ -0,0 +1,626 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{
+    now,
+    structs::{CacheEntry, PicData, PixUrl},
+};
+
+lazy_static! {
+    // 图片元数据、下载和发送记录、缓存索引以及预览等记录都保存在 `pictures/library.db` 中。
+    static ref DB: Mutex<Connection> =
+        Mutex::new(open_db().expect("无法打开数据库 pictures/library.db！"));
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pics (
+    pid INTEGER NOT NULL,
+    p INTEGER NOT NULL,
+    uid INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    author TEXT NOT NULL,
+    r18 INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    ext TEXT NOT NULL,
+    ai_type INTEGER NOT NULL,
+    upload_date INTEGER NOT NULL,
+    urls TEXT NOT NULL,
+    mirror TEXT NOT NULL,
+    PRIMARY KEY (pid, p)
+);
+CREATE INDEX IF NOT EXISTS pics_uid ON pics (uid);
+CREATE INDEX IF NOT EXISTS pics_r18 ON pics (r18);
+CREATE INDEX IF NOT EXISTS pics_ai_type ON pics (ai_type);
+CREATE INDEX IF NOT EXISTS pics_upload_date ON pics (upload_date);
+CREATE TABLE IF NOT EXISTS tags (
+    pid INTEGER NOT NULL,
+    p INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (pid, p, tag)
+);
+CREATE INDEX IF NOT EXISTS tags_tag ON tags (tag);
+CREATE TABLE IF NOT EXISTS downloads (
+    pid INTEGER NOT NULL,
+    p INTEGER NOT NULL,
+    size TEXT NOT NULL,
+    path TEXT NOT NULL,
+    mirror TEXT NOT NULL,
+    bytes INTEGER NOT NULL,
+    time INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS downloads_pid ON downloads (pid, p);
+CREATE TABLE IF NOT EXISTS cache_entries (
+    key TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    mirror TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    etag TEXT NOT NULL,
+    last_modified TEXT NOT NULL,
+    checked_at INTEGER NOT NULL,
+    last_access INTEGER NOT NULL,
+    hits INTEGER NOT NULL,
+    pinned INTEGER NOT NULL,
+    validated INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sends (
+    group_id INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    time INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sends_group_pid ON sends (group_id, pid, time);
+CREATE TABLE IF NOT EXISTS recalls (
+    group_id INTEGER NOT NULL,
+    ids TEXT NOT NULL,
+    internal_ids TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    due INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS recalls_due ON recalls (due);
+CREATE TABLE IF NOT EXISTS previews (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    path TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS previews_group ON previews (group_id, id);
+CREATE TABLE IF NOT EXISTS collages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL,
+    time INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS collages_group ON collages (group_id, id);
+CREATE TABLE IF NOT EXISTS collage_tiles (
+    collage INTEGER NOT NULL,
+    idx INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    p INTEGER NOT NULL,
+    sensitive INTEGER NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (collage, idx)
+);
+CREATE TABLE IF NOT EXISTS fallbacks (
+    time INTEGER NOT NULL,
+    contact_id INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    step TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS fallbacks_time ON fallbacks (time);
+";
+
+fn db_path() -> PathBuf {
+    let mut path = std::env::current_dir().unwrap();
+    path.push("pictures");
+    path.push("library.db");
+    path
+}
+
+fn open_db() -> Result<Connection, Box<dyn Error>> {
+    let path = db_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+// 写入（或更新）一张图片的元数据。
+pub(crate) fn save_pic(pic_data: &PicData) -> Result<(), Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT OR REPLACE INTO pics VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            pic_data.pid,
+            pic_data.p,
+            pic_data.uid,
+            pic_data.title,
+            pic_data.author,
+            pic_data.r18,
+            pic_data.width,
+            pic_data.height,
+            pic_data.ext,
+            pic_data.aiType,
+            pic_data.uploadDate,
+            serde_json::to_string(&pic_data.urls)?,
+            pic_data.mirror,
+        ],
+    )?;
+    tx.execute(
+        "DELETE FROM tags WHERE pid = ?1 AND p = ?2",
+        params![pic_data.pid, pic_data.p],
+    )?;
+    for tag in &pic_data.tags {
+        tx.execute(
+            "INSERT OR IGNORE INTO tags VALUES (?1, ?2, ?3)",
+            params![pic_data.pid, pic_data.p, tag],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn pic_from_row(db: &Connection, row: &Row) -> rusqlite::Result<PicData> {
+    let (pid, p): (i64, i64) = (row.get("pid")?, row.get("p")?);
+    let tags = db
+        .prepare_cached("SELECT tag FROM tags WHERE pid = ?1 AND p = ?2")?
+        .query_map(params![pid, p], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let urls: String = row.get("urls")?;
+    Ok(PicData {
+        pid,
+        p,
+        uid: row.get("uid")?,
+        title: row.get("title")?,
+        author: row.get("author")?,
+        r18: row.get("r18")?,
+        width: row.get("width")?,
+        height: row.get("height")?,
+        tags,
+        ext: row.get("ext")?,
+        aiType: row.get("ai_type")?,
+        uploadDate: row.get("upload_date")?,
+        urls: serde_json::from_str::<PixUrl>(&urls).unwrap_or_default(),
+        mirror: row.get("mirror")?,
+    })
+}
+
+pub(crate) fn load_pic(pid: i64, p: i64) -> Result<Option<PicData>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    Ok(db
+        .query_row(
+            "SELECT * FROM pics WHERE pid = ?1 AND p = ?2",
+            params![pid, p],
+            |row| pic_from_row(&db, row),
+        )
+        .optional()?)
+}
+
+// 按条件查找图片，tags 中的每个标签都要命中，结果随机排列。
+pub(crate) fn find_pics(
+    r18: Option<bool>,
+    tags: &[String],
+    limit: usize,
+) -> Result<Vec<PicData>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let mut sql = "SELECT * FROM pics WHERE 1 = 1".to_string();
+    let mut args: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+    if let Some(r18) = r18 {
+        sql.push_str(" AND r18 = ?");
+        args.push(Box::new(r18));
+    }
+    for tag in tags {
+        sql.push_str(" AND EXISTS (SELECT 1 FROM tags WHERE tags.pid = pics.pid AND tags.p = pics.p AND tag = ?)");
+        args.push(Box::new(tag.clone()));
+    }
+    sql.push_str(" ORDER BY RANDOM() LIMIT ?");
+    args.push(Box::new(limit as i64));
+    let mut stmt = db.prepare(&sql)?;
+    let pics = stmt
+        .query_map(rusqlite::params_from_iter(args.iter()), |row| {
+            pic_from_row(&db, row)
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(pics)
+}
+
+// 所有图片的 pid 和页码。
+pub(crate) fn all_pics() -> Result<Vec<(i64, i64)>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let mut stmt = db.prepare("SELECT pid, p FROM pics")?;
+    let pics = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(pics)
+}
+
+// 删除一张图片的元数据，发送记录保留。
+pub(crate) fn forget_pic(pid: i64, p: i64) -> Result<(), Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    db.execute(
+        "DELETE FROM pics WHERE pid = ?1 AND p = ?2",
+        params![pid, p],
+    )?;
+    db.execute(
+        "DELETE FROM tags WHERE pid = ?1 AND p = ?2",
+        params![pid, p],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn record_download(
+    pid: i64,
+    p: i64,
+    size: &str,
+    path: &Path,
+    mirror: &str,
+    bytes: u64,
+) -> Result<(), Box<dyn Error>> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO downloads VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            pid,
+            p,
+            size,
+            path.to_string_lossy(),
+            mirror,
+            bytes as i64,
+            now()
+        ],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn record_sends(group_id: i64, pids: &[i64], time: i64) -> Result<(), Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    for pid in pids {
+        tx.execute(
+            "INSERT INTO sends VALUES (?1, ?2, ?3)",
+            params![group_id, pid, time],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// 该群在 since 之后是否发送过这个 pid.
+pub(crate) fn sent_since(group_id: i64, pid: i64, since: i64) -> Result<bool, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    Ok(db
+        .query_row(
+            "SELECT 1 FROM sends WHERE group_id = ?1 AND pid = ?2 AND time >= ?3 LIMIT 1",
+            params![group_id, pid, since],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+// 读取整个缓存索引：缓存键 -> 缓存记录。
+pub(crate) fn load_cache_index() -> Result<HashMap<String, CacheEntry>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let index = db
+        .prepare("SELECT * FROM cache_entries")?
+        .query_map([], |row| {
+            Ok((
+                row.get("key")?,
+                CacheEntry {
+                    path: PathBuf::from(row.get::<_, String>("path")?),
+                    mirror: row.get("mirror")?,
+                    sha256: row.get("sha256")?,
+                    size: row.get("size")?,
+                    etag: row.get("etag")?,
+                    last_modified: row.get("last_modified")?,
+                    checked_at: row.get("checked_at")?,
+                    last_access: row.get("last_access")?,
+                    hits: row.get("hits")?,
+                    pinned: row.get("pinned")?,
+                    validated: row.get("validated")?,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+    Ok(index)
+}
+// 写入（或更新）若干条缓存记录。
+pub(crate) fn save_cache_entries(entries: &[(&str, &CacheEntry)]) -> Result<(), Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    for (key, entry) in entries {
+        tx.execute(
+            "INSERT OR REPLACE INTO cache_entries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                key,
+                entry.path.to_string_lossy(),
+                entry.mirror,
+                entry.sha256,
+                entry.size,
+                entry.etag,
+                entry.last_modified,
+                entry.checked_at,
+                entry.last_access,
+                entry.hits,
+                entry.pinned,
+                entry.validated,
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+pub(crate) fn delete_cache_entries(keys: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    for key in keys {
+        tx.execute("DELETE FROM cache_entries WHERE key = ?1", params![key])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// 记录一次发送时用到的退路。
+pub(crate) fn record_fallback(contact_id: i64, pid: i64, step: &str) -> Result<(), Box<dyn Error>> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO fallbacks VALUES (?1, ?2, ?3, ?4)",
+        params![now(), contact_id, pid, step],
+    )?;
+    Ok(())
+}
+// 统计 since 之后各个退路被用到的次数。
+pub(crate) fn fallback_counts(since: i64) -> Result<Vec<(String, i64)>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let counts = db
+        .prepare(
+            "SELECT step, COUNT(*) FROM fallbacks WHERE time >= ?1 GROUP BY step ORDER BY COUNT(*) DESC",
+        )?
+        .query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(counts)
+}
+
+// 登记一张预览图对应的原图，只保留最近的 max_entries 条，返回预览编号。
+pub(crate) fn save_preview(
+    group_id: i64,
+    pid: i64,
+    path: &Path,
+    max_entries: usize,
+) -> Result<i64, Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT INTO previews (group_id, pid, path) VALUES (?1, ?2, ?3)",
+        params![group_id, pid, path.to_string_lossy()],
+    )?;
+    let id = tx.last_insert_rowid();
+    tx.execute(
+        "DELETE FROM previews WHERE id <= ?1",
+        params![id - max_entries as i64],
+    )?;
+    tx.commit()?;
+    Ok(id)
+}
+// 查询本群的一张预览对应的 pid 和原图，id 为 None 时取本群最近的一张。
+pub(crate) fn load_preview(
+    group_id: i64,
+    id: Option<u64>,
+) -> Result<Option<(i64, PathBuf)>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let row = |row: &Row| Ok((row.get(0)?, PathBuf::from(row.get::<_, String>(1)?)));
+    let preview = match id {
+        Some(id) => db.query_row(
+            "SELECT pid, path FROM previews WHERE id = ?1 AND group_id = ?2",
+            params![id as i64, group_id],
+            row,
+        ),
+        None => db.query_row(
+            "SELECT pid, path FROM previews WHERE group_id = ?1 ORDER BY id DESC LIMIT 1",
+            params![group_id],
+            row,
+        ),
+    };
+    Ok(preview.optional()?)
+}
+
+// 登记一张拼图及其每一格对应的图片（序号从 1 开始），返回拼图编号。
+pub(crate) fn register_collage(
+    group_id: i64,
+    tiles: &[(i64, i64, bool, &Path)],
+) -> Result<i64, Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    tx.execute(
+        "INSERT INTO collages (group_id, time) VALUES (?1, ?2)",
+        params![group_id, now()],
+    )?;
+    let id = tx.last_insert_rowid();
+    for (idx, (pid, p, sensitive, path)) in tiles.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO collage_tiles VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                idx as i64 + 1,
+                pid,
+                p,
+                sensitive,
+                path.to_string_lossy()
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(id)
+}
+// 查询拼图中某一格的 pid、是否敏感和图片路径，collage 为 None 时取本群最近的一张拼图。
+pub(crate) fn collage_tile(
+    group_id: i64,
+    collage: Option<u64>,
+    idx: u64,
+) -> Result<Option<(i64, bool, PathBuf)>, Box<dyn Error>> {
+    let db = DB.lock().unwrap();
+    let collage = match collage {
+        Some(id) => db
+            .query_row(
+                "SELECT id FROM collages WHERE id = ?1 AND group_id = ?2",
+                params![id as i64, group_id],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?,
+        None => db.query_row(
+            "SELECT MAX(id) FROM collages WHERE group_id = ?1",
+            params![group_id],
+            |row| row.get::<_, Option<i64>>(0),
+        )?,
+    };
+    let Some(collage) = collage else {
+        return Ok(None);
+    };
+    Ok(db
+        .query_row(
+            "SELECT pid, sensitive, path FROM collage_tiles WHERE collage = ?1 AND idx = ?2",
+            params![collage, idx as i64],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, bool>(1)?,
+                    PathBuf::from(row.get::<_, String>(2)?),
+                ))
+            },
+        )
+        .optional()?)
+}
+
+// 登记一条待撤回的消息，ids、internal_ids 和 time 用于重建消息源。
+pub(crate) fn schedule_recall(
+    group_id: i64,
+    ids: &[i32],
+    internal_ids: &[i32],
+    time: i32,
+    due: i64,
+) -> Result<(), Box<dyn Error>> {
+    DB.lock().unwrap().execute(
+        "INSERT INTO recalls VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            group_id,
+            serde_json::to_string(ids)?,
+            serde_json::to_string(internal_ids)?,
+            time,
+            due
+        ],
+    )?;
+    Ok(())
+}
+
+// 取出所有到期的待撤回消息：(群号, ids, internal_ids, time).
+pub(crate) fn take_due_recalls(
+    now: i64,
+) -> Result<Vec<(i64, Vec<i32>, Vec<i32>, i32)>, Box<dyn Error>> {
+    let mut db = DB.lock().unwrap();
+    let tx = db.transaction()?;
+    let recalls = tx
+        .prepare("SELECT group_id, ids, internal_ids, time FROM recalls WHERE due <= ?1")?
+        .query_map(params![now], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i32>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    tx.execute("DELETE FROM recalls WHERE due <= ?1", params![now])?;
+    tx.commit()?;
+    Ok(recalls
+        .into_iter()
+        .map(|(group_id, ids, internal_ids, time)| {
+            (
+                group_id,
+                serde_json::from_str(&ids).unwrap_or_default(),
+                serde_json::from_str(&internal_ids).unwrap_or_default(),
+                time,
+            )
+        })
+        .collect())
+}
+
+// 导入 `pictures/metadata/*.toml`, 返回导入和失败的条数。
+// only_missing 为 true 时跳过数据库中已有的图片，以免覆盖更新的记录。
+pub(crate) fn import_metadata(only_missing: bool) -> (usize, usize) {
+    let mut dir = std::env::current_dir().unwrap();
+    dir.push("pictures");
+    dir.push("metadata");
+    let mut imported = 0;
+    let mut failed = 0;
+    let Ok(metas) = fs::read_dir(dir) else {
+        return (imported, failed);
+    };
+    for meta in metas.flatten() {
+        let path = meta.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let pic_data = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|meta| toml::from_str::<PicData>(&meta).map_err(|err| err.to_string()));
+        if only_missing
+            && let Ok(pic_data) = &pic_data
+            && load_pic(pic_data.pid, pic_data.p).is_ok_and(|pic| pic.is_some())
+        {
+            continue;
+        }
+        match pic_data.map(|pic_data| save_pic(&pic_data).map_err(|err| err.to_string())) {
+            Ok(Ok(())) => imported += 1,
+            Ok(Err(err)) | Err(err) => {
+                eprintln!("无法导入 {}：{}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+    (imported, failed)
+}
+
+// 导入旧版本的 `pictures/metadata/*.toml` 和 `pictures/history.json`.
+pub(crate) fn import_legacy() {
+    let (imported, failed) = import_metadata(false);
+    println!("导入了 {} 条元数据，失败 {} 条。", imported, failed);
+    let mut path = std::env::current_dir().unwrap();
+    path.push("pictures");
+    path.push("history.json");
+    let history = fs::read_to_string(path)
+        .ok()
+        .and_then(|history| serde_json::from_str::<HashMap<i64, HashMap<i64, i64>>>(&history).ok())
+        .unwrap_or_default();
+    let mut sends = 0;
+    for (group_id, pids) in history {
+        for (pid, time) in pids {
+            if record_sends(group_id, &[pid], time).is_ok() {
+                sends += 1;
+            }
+        }
+    }
+    println!("导入了 {} 条发送记录。", sends);
+    let mut path = std::env::current_dir().unwrap();
+    path.push("pictures");
+    path.push("cache.json");
+    let index = fs::read_to_string(path)
+        .ok()
+        .and_then(|index| serde_json::from_str::<HashMap<String, CacheEntry>>(&index).ok())
+        .unwrap_or_default();
+    let entries = index
+        .iter()
+        .map(|(key, entry)| (key.as_str(), entry))
+        .collect::<Vec<_>>();
+    match save_cache_entries(&entries) {
+        Ok(()) => println!("导入了 {} 条缓存记录。", entries.len()),
+        Err(err) => eprintln!("无法导入缓存记录：{}", err),
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, StatusCode,
+};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncWriteExt, sync::Semaphore};
+use url::Url;
+
+use super::structs::{DownloadConfig, Downloaded};
+use crate::CONFIG;
+
+lazy_static! {
+    // 所有下载共用的下载服务。
+    pub(crate) static ref DOWNLOADER: DownloadService = DownloadService::new(&CONFIG.download);
+}
+
+// 限制并发数和带宽，并记录下载进度的下载服务。
+pub(crate) struct DownloadService {
+    global: Semaphore,
+    per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    // 每秒字节数，为零时不限速。
+    bandwidth: u64,
+    // 令牌桶中剩余的字节数和上次补充的时间，可以为负，表示需要等待。
+    bucket: Mutex<(f64, Instant)>,
+    retries: u32,
+    timeout: Duration,
+    // 路径 -> (已下载的字节数, 总字节数)。
+    progress: Mutex<HashMap<PathBuf, (u64, Option<u64>)>>,
+}
+
+// 下载中（或写入中）的临时文件。
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".part");
+    PathBuf::from(tmp_path)
+}
+
+// 先写入临时文件再重命名，避免之后复用写了一半的文件；失败时删除临时文件。
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let tmp_path = part_path(path);
+    let result = write(&tmp_path).and_then(|()| Ok(std::fs::rename(&tmp_path, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+impl DownloadService {
+    pub(crate) fn new(config: &DownloadConfig) -> Self {
+        Self {
+            global: Semaphore::new(config.concurrency.max(1)),
+            per_host: config.per_host.max(1),
+            hosts: Mutex::new(HashMap::new()),
+            bandwidth: config.bandwidth,
+            bucket: Mutex::new((config.bandwidth as f64, Instant::now())),
+            retries: config.retries,
+            timeout: Duration::from_secs(config.timeout),
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 正在进行的下载及其进度。
+    pub(crate) fn progress(&self) -> Vec<(PathBuf, u64, Option<u64>)> {
+        self.progress
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, &(done, total))| (path.clone(), done, total))
+            .collect()
+    }
+
+    async fn throttle(&self, bytes: u64) {
+        if self.bandwidth == 0 {
+            return;
+        }
+        let wait = {
+            let rate = self.bandwidth as f64;
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            bucket.0 = (bucket.0 + now.duration_since(bucket.1).as_secs_f64() * rate).min(rate);
+            bucket.1 = now;
+            bucket.0 -= bytes as f64;
+            Duration::from_secs_f64((-bucket.0 / rate).max(0.0))
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // 带上 validators (ETag, Last-Modified) 时为条件请求，服务器返回 304 时得到 None.
+    // 失败时按配置重试，客户端错误（429 除外）不重试。
+    pub(crate) async fn download(
+        &self,
+        client: &Client,
+        url: &Url,
+        path: &Path,
+        validators: Option<(&str, &str)>,
+    ) -> Result<Option<Downloaded>, Box<dyn Error>> {
+        let host = url.host_str().unwrap_or_default().to_string();
+        let host = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_host)))
+            .clone();
+        // 先排主机的队，避免等待繁忙主机的下载占住全局名额，拖住其他镜像的下载。
+        let _host = host.acquire().await?;
+        let _global = self.global.acquire().await?;
+        let mut attempt = 0;
+        let result = loop {
+            let result = if self.timeout.is_zero() {
+                self.download_once(client, url, path, validators).await
+            } else {
+                tokio::time::timeout(
+                    self.timeout,
+                    self.download_once(client, url, path, validators),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    // 超时时 download_once 被直接丢弃，来不及删除临时文件。
+                    let _ = std::fs::remove_file(part_path(path));
+                    Err("下载超时。".into())
+                })
+            };
+            let retryable = match &result {
+                Ok(_) => false,
+                Err(err) => !err
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(|err| err.status())
+                    .is_some_and(|status| {
+                        status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS
+                    }),
+            };
+            if !retryable || attempt >= self.retries {
+                break result;
+            }
+            attempt += 1;
+            eprintln!("下载 {} 失败，第 {} 次重试。", url, attempt);
+            tokio::time::sleep(Duration::from_secs(attempt.into())).await;
+        };
+        self.progress.lock().unwrap().remove(path);
+        result
+    }
+
+    // 内容先写入临时文件，写完后再重命名，避免上传到写了一半的文件。
+    async fn download_once(
+        &self,
+        client: &Client,
+        url: &Url,
+        path: &Path,
+        validators: Option<(&str, &str)>,
+    ) -> Result<Option<Downloaded>, Box<dyn Error>> {
+        let mut req = client.get(url.clone());
+        if let Some((etag, last_modified)) = validators {
+            if !etag.is_empty() {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if !last_modified.is_empty() {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let resp = req.send().await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let mut resp = resp.error_for_status()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let total = resp.content_length();
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = part_path(path);
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        self.progress
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (0, total));
+        let write = async {
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            while let Some(chunk) = resp.chunk().await? {
+                hasher.update(&chunk);
+                size += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+                if let Some(progress) = self.progress.lock().unwrap().get_mut(path) {
+                    progress.0 = size;
+                }
+                self.throttle(chunk.len() as u64).await;
+            }
+            file.flush().await?;
+            Ok::<_, Box<dyn Error>>(())
+        };
+        if let Err(err) = write.await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(err);
+        }
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(Some(Downloaded {
+            sha256: format!("{:x}", hasher.finalize()),
+            size,
+            etag,
+            last_modified,
+        }))
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use strfmt::strfmt;
+
+use super::{
+    record_fallback,
+    structs::{FallbackConfig, Outcome},
+};
+use crate::{prelude::*, CONFIG};
+
+// 记下用到的退路：打印出来，并存进数据库供管理员用指令查看。
+pub(crate) fn log_fallback(contact_id: i64, pid: i64, step: &str) {
+    eprintln!("向 {} 发送 {} 时使用退路：{}", contact_id, pid, step);
+    if let Err(err) = record_fallback(contact_id, pid, step) {
+        eprintln!("无法记录退路：{}", err);
+    }
+}
+
+// 单独发送一张图片。content 为 None 表示图片已经上传失败（重试和更小的尺寸都用过了）。
+// 发送失败时依次：重试，换用更小的尺寸重新上传，改为只有这一张图片的合并转发，只发送说明和作品链接。
+// group_id 和 private 与 build_content 的参数相同，用于重新上传。
+pub(crate) async fn send_with_fallback<C: ContactTrait + SendMessageSupportedTrait>(
+    contact: &C,
+    at: Option<i64>,
+    group_id: i64,
+    private: bool,
+    pic_msg: &PicMsg,
+    content: Option<MessageChain>,
+    config: &FallbackConfig,
+) -> Outcome<C> {
+    let contact_id = contact.get_id();
+    let with_at = |content: MessageChain| match at {
+        Some(id) => At::new(id).plus(content),
+        None => content,
+    };
+    if let Some(content) = content {
+        let msg = with_at(content);
+        for attempt in 0..=config.retries {
+            if attempt > 0 {
+                log_fallback(contact_id, pic_msg.pid, "重试发送");
+            }
+            if let Some(receipt) = try_send(contact, &msg) {
+                return Outcome::Sent(receipt);
+            }
+        }
+        // 可能是图片超出了大小限制。
+        for candidate in &pic_msg.fallbacks {
+            log_fallback(contact_id, pic_msg.pid, "更小尺寸");
+            if let Some(content) =
+                build_smaller_content(contact, group_id, pic_msg, private, candidate).await
+                && let Some(receipt) = try_send(contact, &with_at(content))
+            {
+                return Outcome::Sent(receipt);
+            }
+        }
+        if config.forward {
+            log_fallback(contact_id, pic_msg.pid, "合并转发");
+            let time = now() as i32;
+            let forward = ForwardMessageBuilder::new(contact)
+                .add(CONFIG.bot.bot_id, &CONFIG.delivery.sender_name, &msg, time)
+                .build();
+            if let Some(receipt) = try_send(contact, &forward) {
+                return Outcome::Sent(receipt);
+            }
+        }
+    }
+    let text = if config.link {
+        log_fallback(contact_id, pic_msg.pid, "文字链接");
+        let mut tmp = HashMap::new();
+        tmp.insert("doc".to_string(), pic_msg.doc.clone());
+        tmp.insert("pid".to_string(), pic_msg.pid.to_string());
+        strfmt(&config.tip_link, &tmp).unwrap()
+    } else {
+        format!("{}{}", pic_msg.doc, CONFIG.err_msg.bad_dld)
+    };
+    let sent = match at {
+        Some(id) => try_send(contact, &At::new(id).plus(PlainText::from(text))),
+        None => try_send(contact, &PlainText::from(text)),
+    };
+    match sent {
+        Some(_) => Outcome::Linked,
+        None => Outcome::Failed,
+    }
+}
@@ -0,0 +1,35 @@
+use std::{collections::HashMap, fs, sync::Arc, sync::Mutex};
+
+use ab_glyph::FontVec;
+use lazy_static::lazy_static;
+
+// 内置的字体（DejaVu Sans），只有拉丁字母、数字等，没有中文。
+static BUNDLED: &[u8] = include_bytes!("../../fonts/DejaVuSans.ttf");
+
+lazy_static! {
+    static ref BUNDLED_FONT: Arc<FontVec> =
+        Arc::new(FontVec::try_from_vec(BUNDLED.to_vec()).expect("内置的字体无法加载！"));
+    // 字体文件路径 -> 加载好的字体，加载失败时记下内置的字体，避免反复读取。
+    static ref FONTS: Mutex<HashMap<String, Arc<FontVec>>> = Mutex::new(HashMap::new());
+}
+
+// 加载配置的字体文件（ttf/otf），路径为空或无法加载时使用内置的字体。
+pub(crate) fn load_font(path: &str) -> Arc<FontVec> {
+    FONTS
+        .lock()
+        .unwrap()
+        .entry(path.to_string())
+        .or_insert_with(|| {
+            if !path.is_empty() {
+                let font = fs::read(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|data| FontVec::try_from_vec(data).map_err(|err| err.to_string()));
+                match font {
+                    Ok(font) => return Arc::new(font),
+                    Err(err) => eprintln!("无法加载字体 {}，改用内置的字体：{}", path, err),
+                }
+            }
+            BUNDLED_FONT.clone()
+        })
+        .clone()
+}
@@ -0,0 +1,37 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use lazy_static::lazy_static;
+use strfmt::strfmt;
+
+use super::structs::{Cmd, FriendConfig};
+use crate::prelude::*;
+
+lazy_static! {
+    // 好友 -> 上一条被接受的指令的时间。
+    static ref LAST_CMD: Mutex<HashMap<i64, Instant>> = Mutex::new(HashMap::new());
+}
+
+// 好友是否可以使用机器人。
+pub(crate) fn friend_allowed(friend: &Friend, config: &FriendConfig) -> bool {
+    config.enabled && (config.friends.is_empty() || config.friends.contains(&friend.get_id()))
+}
+
+// 按私聊的冷却时间和 r18 策略检查指令，不能执行时回复原因并返回 false.
+pub(crate) fn friend_may_request(cmd: &Cmd, friend: &Friend, config: &FriendConfig) -> bool {
+    if cmd.r18 != 0 && !config.allow_r18 {
+        friend.send_string(&config.bad_r18);
+        return false;
+    }
+    let mut last_cmd = LAST_CMD.lock().unwrap();
+    if let Some(last) = last_cmd.get(&friend.get_id()) {
+        let elapsed = last.elapsed().as_secs();
+        if elapsed < config.cooldown {
+            let mut tmp = HashMap::new();
+            tmp.insert("n".to_string(), (config.cooldown - elapsed).to_string());
+            friend.send_string(&strfmt(&config.bad_cd, &tmp).unwrap());
+            return false;
+        }
+    }
+    last_cmd.insert(friend.get_id(), Instant::now());
+    true
+}
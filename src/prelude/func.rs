@@ -1,15 +1,33 @@
-use std::{collections::HashMap, error::Error, fs, num::ParseIntError, path::PathBuf};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    num::ParseIntError,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use super::structs::{Config, ReqData};
-use crate::{prelude::*, CONFIG};
+use super::structs::{Config, HttpConfig, ReqData};
+use crate::{prelude::*, CLIENT, CONFIG};
 use chinese_number::{ChineseCountMethod, ChineseToNumber, ChineseToNumberError};
-use futures::{channel::mpsc::UnboundedSender, future::join_all};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::join_all,
+    stream::{FuturesOrdered, StreamExt},
+};
 use rand::Rng;
 use regex::Match;
-use reqwest::Response;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, REFERER},
+    Certificate, Client, Proxy,
+};
 use strfmt::strfmt;
-use tokio::{io::AsyncWriteExt, join, sync::Mutex};
-use trauma::{download::Download, downloader::Downloader};
 use url::Url;
 
 pub(crate) fn zh2num(s: &str) -> Result<i128, ChineseToNumberError> {
@@ -64,13 +82,16 @@ pub(crate) fn zh2num(s: &str) -> Result<i128, ChineseToNumberError> {
     ir.to_number(ChineseCountMethod::High)
 }
 
-pub(crate) fn rxcap(
-    cap: regex::Captures<'_>,
-) -> Result<(i128, u8, Vec<String>, bool), Box<dyn Error>> {
+pub(crate) fn rxcap(cap: regex::Captures<'_>) -> Result<Cmd, Box<dyn Error>> {
     let mut pic_count = 1;
     let mut r18 = 0; // 默认是非 r18 模式。
     let tags;
     let mut ai = true;
+    let mut dup = false;
+    let mut forward = None;
+    let mut private = false;
+    let mut files = false;
+    let mut collage = false;
     if let Some(hans_num) = cap.name("hans_num")
         && !hans_num.is_empty()
     {
@@ -107,6 +128,35 @@ pub(crate) fn rxcap(
     {
         ai = false;
     }
+    if let Some(dup_tmp) = cap.name("dup")
+        && !dup_tmp.is_empty()
+    {
+        dup = true;
+    }
+    if let Some(fwd) = cap.name("fwd")
+        && !fwd.is_empty()
+    {
+        forward = Some(true);
+    } else if let Some(sep) = cap.name("sep")
+        && !sep.is_empty()
+    {
+        forward = Some(false);
+    }
+    if let Some(pm) = cap.name("pm")
+        && !pm.is_empty()
+    {
+        private = true;
+    }
+    if let Some(file) = cap.name("file")
+        && !file.is_empty()
+    {
+        files = true;
+    }
+    if let Some(grid) = cap.name("grid")
+        && !grid.is_empty()
+    {
+        collage = true;
+    }
     tags = get_tags(cap.name("tags"));
 
     println!("{:?}", cap.name("hans_num"));
@@ -120,7 +170,17 @@ pub(crate) fn rxcap(
         println!("{:?}", tag);
     }
 
-    Ok((pic_count, r18, tags, ai))
+    Ok(Cmd {
+        num: pic_count,
+        r18,
+        tags,
+        ai,
+        dup,
+        forward,
+        private,
+        files,
+        collage,
+    })
 }
 
 // 该函数不返回 None
@@ -165,50 +225,104 @@ pub(crate) fn get_tags(cap: Option<Match<'_>>) -> Vec<String> {
     tags
 }
 
-pub(crate) fn build_req_data(
-    num: i128,
-    r18: u8,
-    tag: Vec<String>,
-    ai: bool,
-    group: &Group,
-    config: &Config,
-) -> ReqData {
+// 返回值中的每个 ReqData 对应一次 api 请求，数量都不超过 api 的限制。
+pub(crate) fn build_req_data(cmd: &Cmd, chat: &Chat, config: &Config) -> Vec<ReqData> {
+    let num = cmd.num;
     let n = {
         let mut n = HashMap::new();
         n.insert("n".to_string(), num.to_string());
         n
     };
     // DEFAULT -- tip_cmd = "收到指令：获取{n}张色图。正在处理中……"
-    group.send_string(&strfmt(&config.tip_msg.tip_cmd, &n).unwrap());
-    let mut req_data = config.default_req.clone();
-    req_data.r18 = r18;
-    if num > 9_4266 {
-        req_data.num = rand::thread_rng().gen_range(1..=20);
+    chat.send_string(&strfmt(&config.tip_msg.tip_cmd, &n).unwrap());
+    let role_max = match chat {
+        Chat::Group(_, member) if config.prem.admins.contains(&member.get_id()) => {
+            config.limit.admin_max
+        }
+        Chat::Group(..) => config.limit.member_max,
+        Chat::Friend(_) => config.friend.max,
+    }
+    // 上限配置为 0 时按 1 处理，否则 gen_range 会 panic.
+    .max(1);
+    let total: u32;
+    if num > config.limit.db_total {
+        total = rand::thread_rng().gen_range(1..=role_max);
         let n = {
             let mut n = HashMap::new();
-            n.insert("n".to_string(), req_data.num.to_string());
+            n.insert("n".to_string(), total.to_string());
             n
         };
         // 请求的数量超过了数据库总量。
-        group.send_string(&strfmt(&config.err_msg.bad_hug, &n).unwrap());
-    } else if num > 20 {
-        req_data.num = rand::thread_rng().gen_range(1..=20);
+        chat.send_string(&strfmt(&config.err_msg.bad_hug, &n).unwrap());
+    } else if num > role_max.into() {
+        total = rand::thread_rng().gen_range(1..=role_max);
         let n: HashMap<String, String> = {
             let mut n = HashMap::new();
-            n.insert("n".to_string(), req_data.num.to_string());
+            n.insert("n".to_string(), total.to_string());
             n
         };
-        // 请求的数字超过 api 限制。
-        group.send_string(&strfmt(&config.err_msg.bad_lim, &n).unwrap());
+        // 请求的数字超过了允许的上限。
+        chat.send_string(&strfmt(&config.err_msg.bad_lim, &n).unwrap());
     } else {
-        req_data.num = if num == 0 { 1 } else { num as u8 };
+        total = if num <= 0 { 1 } else { num as u32 };
+    }
+    let mut req_data = config.default_req.clone();
+    req_data.r18 = cmd.r18;
+    // 需要所有尺寸才能在超出上传限制时降级。
+    if req_data.size.is_empty() {
+        req_data.size = PIC_SIZES.iter().map(|size| size.to_string()).collect();
     }
-    req_data.tag = tag;
-    req_data.excludeAI = ai;
-    req_data
+    req_data.tag = cmd.tags.clone();
+    req_data.excludeAI = cmd.ai;
+    split_batches(&req_data, total, config.limit.api_max)
 }
 
-pub(crate) fn handle_err(err: Box<dyn Error>, group: &Group, config: &Config) {
+// 超过 api 单次限制的数量拆分为多次请求。
+pub(crate) fn split_batches(req_data: &ReqData, total: u32, api_max: u8) -> Vec<ReqData> {
+    let api_max = api_max.max(1);
+    let mut batches = Vec::new();
+    let mut rest = total;
+    while rest > 0 {
+        let mut batch = req_data.clone();
+        batch.num = rest.min(api_max.into()) as u8;
+        rest -= batch.num as u32;
+        batches.push(batch);
+    }
+    batches
+}
+
+// 并行发送请求，返回所有的图片数据、api 返回的错误和请求失败的次数。
+pub(crate) async fn fetch(req_data: &[ReqData]) -> (Vec<PicData>, Vec<String>, usize) {
+    let send_posts = req_data
+        .iter()
+        .map(|req_data| CLIENT.post(&CONFIG.api_url).json(req_data).send());
+    let mut data = Vec::new();
+    let mut errors = Vec::new();
+    let mut failed = 0;
+    for send_post in join_all(send_posts).await {
+        let resq_data: RespData = match send_post {
+            Ok(resp) => match resp.json().await {
+                Ok(resq_data) => resq_data,
+                Err(err) => {
+                    errors.push(err.to_string());
+                    continue;
+                }
+            },
+            Err(err) => {
+                eprintln!("请求失败：{}", err);
+                failed += 1;
+                continue;
+            }
+        };
+        if !resq_data.error.is_empty() {
+            errors.push(resq_data.error);
+        }
+        data.extend(resq_data.data);
+    }
+    (data, errors, failed)
+}
+
+pub(crate) fn handle_err(err: Box<dyn Error>, chat: &Chat, config: &Config) {
     if let Some(err) = err.downcast_ref::<ChineseToNumberError>() {
         match err {
             ChineseToNumberError::ChineseNumberIncorrect { char_index } => {
@@ -218,102 +332,319 @@ pub(crate) fn handle_err(err: Box<dyn Error>, group: &Group, config: &Config) {
                     n
                 };
                 // 数字格式不正确。
-                group.send_string(&strfmt(&config.err_msg.bad_num, &n).unwrap());
+                chat.send_string(&strfmt(&config.err_msg.bad_num, &n).unwrap());
             }
             ChineseToNumberError::Overflow | ChineseToNumberError::Underflow => {
                 // 数据溢出。
-                group.send_string(&&config.err_msg.bad_int);
+                chat.send_string(&config.err_msg.bad_int);
             }
             _ => {
                 // 意料之外的错误。
-                group.send_string(&config.err_msg.bad_bad);
+                chat.send_string(&config.err_msg.bad_bad);
                 println!("{}", err);
             }
         }
     } else if let Some(err) = err.downcast_ref::<ParseIntError>() {
         match err.kind() {
             std::num::IntErrorKind::PosOverflow | std::num::IntErrorKind::NegOverflow => {
-                group.send_string(&&config.err_msg.bad_int);
+                chat.send_string(&config.err_msg.bad_int);
             }
             _ => {
                 // 意料之外的错误。
-                group.send_string(&config.err_msg.bad_bad);
+                chat.send_string(&config.err_msg.bad_bad);
                 println!("{}", err);
             }
         }
     }
 }
 
+pub(crate) fn build_client(http: &HttpConfig) -> Result<Client, Box<dyn Error>> {
+    let mut builder = Client::builder();
+    if !http.proxy.is_empty() {
+        builder = builder.proxy(Proxy::all(&http.proxy)?);
+    }
+    if http.connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(http.connect_timeout));
+    }
+    if http.read_timeout > 0 {
+        builder = builder.read_timeout(Duration::from_secs(http.read_timeout));
+    }
+    if !http.user_agent.is_empty() {
+        builder = builder.user_agent(&http.user_agent);
+    }
+    let mut headers = HeaderMap::new();
+    for (name, value) in &http.headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    // pixiv 的图片服务器会校验 Referer.
+    if !http.referer.is_empty() {
+        headers.insert(REFERER, HeaderValue::from_str(&http.referer)?);
+    }
+    builder = builder.default_headers(headers);
+    for ca_cert in &http.ca_certs {
+        let pem = fs::read(ca_cert)?;
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+    Ok(builder.build()?)
+}
+
+// 图片按 pid 分目录存放，文件名中带上页码和尺寸，形如 `pictures/12/12345678_p0.original.jpg`.
+pub(crate) fn pic_path_of(pid: i64, p: i64, size: &str, ext: &str) -> PathBuf {
+    let mut pic_path = std::env::current_dir().unwrap();
+    pic_path.push("pictures");
+    pic_path.push((pid / 1_000_000).to_string());
+    pic_path.push(format!("{}_p{}.{}.{}", pid, p, size, ext));
+    pic_path
+}
+
+// 旧版本的元数据以 pid 和页码命名，形如 `pictures/metadata/12345678_p0.toml`, 现在只有迁移和导入时会用到。
+pub(crate) fn meta_path_of(pid: i64, p: i64) -> PathBuf {
+    let mut meta_path = std::env::current_dir().unwrap();
+    meta_path.push("pictures");
+    meta_path.push("metadata");
+    meta_path.push(format!("{}_p{}.toml", pid, p));
+    meta_path
+}
+
+// 按尺寸从大到小排列的候选地址，像素数超出限制的尺寸会被跳过，但至少保留最小的一个。
+pub(crate) fn size_candidates(pic_data: &PicData, max_pixels: u64) -> Vec<(&'static str, Url)> {
+    let mut candidates = PIC_SIZES
+        .iter()
+        .filter_map(|&size| {
+            let url = Url::parse(pic_data.urls.get(size)).ok()?;
+            Some((size, url))
+        })
+        .collect::<Vec<_>>();
+    if max_pixels > 0 {
+        let pixels = |size: &str| {
+            let (w, h) = (pic_data.width.max(1), pic_data.height.max(1));
+            let scale = match PixUrl::max_side(size) {
+                Some(side) => (side as f64 / w.max(h) as f64).min(1.0),
+                None => 1.0,
+            };
+            (w as f64 * scale) as u64 * (h as f64 * scale) as u64
+        };
+        while candidates.len() > 1 && pixels(candidates[0].0) > max_pixels {
+            candidates.remove(0);
+        }
+    }
+    candidates
+}
+
+// 当前的 Unix 时间戳（秒）。
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// 取出 mirai 抛出的异常信息。
+fn panic_reason(err: Box<dyn Any + Send>) -> String {
+    err.downcast_ref::<String>()
+        .cloned()
+        .or_else(|| err.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or_else(|| "未知错误".to_string())
+}
+
+// 上传失败时 mirai 会抛出异常，这里记下原因并将其转为 None.
+pub(crate) fn try_upload<C: ContactTrait>(contact: &C, path: &Path) -> Option<Image> {
+    let path_str = path.to_str()?;
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        contact.upload_image_from_file(path_str)
+    }))
+    .map_err(|err| eprintln!("上传 {} 失败：{}", path.display(), panic_reason(err)))
+    .ok()
+}
+
+// 发送消息，失败（例如被风控拦截、被禁言）时记下原因并返回 None.
+pub(crate) fn try_send<C: SendMessageSupportedTrait, M: MessageTrait>(
+    contact: &C,
+    msg: &M,
+) -> Option<MessageReceipt<C>> {
+    std::panic::catch_unwind(AssertUnwindSafe(|| contact.send_message(msg)))
+        .map_err(|err| {
+            eprintln!(
+                "向 {} 发送消息失败：{}",
+                contact.get_id(),
+                panic_reason(err)
+            )
+        })
+        .ok()
+}
+
+// 选出要发送的尺寸并下载，同时保存元数据。
+// 返回选中尺寸的路径、上传失败时依次尝试的更小尺寸，以及是否下载成功。
+pub(crate) async fn download_pic(
+    pic_data: &mut PicData,
+) -> (PathBuf, Vec<(&'static str, PathBuf, Url)>, bool) {
+    let mut candidates = size_candidates(pic_data, CONFIG.upload.max_pixels)
+        .into_iter()
+        .map(|(size, url)| {
+            let ext = Path::new(url.path())
+                .extension()
+                .map_or("jpg".to_string(), |ext| ext.to_string_lossy().to_string());
+            (size, pic_path_of(pic_data.pid, pic_data.p, size, &ext), url)
+        })
+        .collect::<Vec<_>>();
+    // 选出字节数也在限制内的最大尺寸，更小的尺寸留作上传失败时的退路。
+    if CONFIG.upload.max_bytes > 0 {
+        while candidates.len() > 1 {
+            let (_, pic_path, url) = &candidates[0];
+            let len = match fs::metadata(pic_path) {
+                Ok(meta) => Some(meta.len()),
+                Err(_) => remote_size(&CLIENT, url, &CONFIG.mirror).await,
+            };
+            if len.is_some_and(|len| len > CONFIG.upload.max_bytes) {
+                candidates.remove(0);
+            } else {
+                break;
+            }
+        }
+    }
+    let (size, pic_path, url) = candidates.remove(0);
+    let dims = (pic_data.width, pic_data.height);
+    let downloaded = match fetch_cached(pic_data.pid, pic_data.p, size, dims, &url, &pic_path).await
+    {
+        Ok(mirror) => {
+            pic_data.mirror = mirror;
+            true
+        }
+        Err(err) => {
+            eprintln!("下载 {} 失败：{}", url, err);
+            false
+        }
+    };
+    if let Err(err) = save_pic(pic_data) {
+        eprintln!("无法保存 {} 的元数据：{}", pic_data.pid, err);
+    }
+    (pic_path, candidates, downloaded)
+}
+
 // task 干的事情：
 //      发送 post 请求。
 //      获取响应数据然后异步地下载图片和构造不包含图片的 MessageChain.
 pub(crate) async fn task(
-    lq_tx: UnboundedSender<(Group, Member, Mutex<HashMap<PathBuf, MessageChain>>)>,
-    downloader: Downloader,
-    group: Group,
-    member: Member,
-    req_data: ReqData,
-    send_post: Result<Response, reqwest::Error>,
+    lq_tx: UnboundedSender<Delivery>,
+    chat: Chat,
+    cmd: Cmd,
+    req_data: Vec<ReqData>,
+    ticket: Ticket,
 ) {
-    if send_post.is_err() {
-        // 请求失败。
-        group.send_string(&CONFIG.err_msg.bad_req.clone());
-        return;
-    }
-    let resq_data: RespData = send_post.unwrap().json().await.unwrap();
-    let error = resq_data.error;
-    if !error.is_empty() {
-        let mut tmp = HashMap::new();
-        tmp.insert("msg".to_string(), error.clone());
-        // 响应失败。
-        group.send_string(&strfmt(&CONFIG.err_msg.bad_rsp.clone(), &tmp).unwrap());
-        return;
-    }
-    let data = resq_data.data;
-    // println!("响应图片数量：{}", resq_data_len);
+    let total: usize = req_data.iter().map(|req_data| req_data.num as usize).sum();
+    // 先从预取池中取图，不够的再请求 api.
+    let mut data = take_prefetched(&cmd, chat.id(), total, &CONFIG.prefetch);
+    let req_data = if data.is_empty() {
+        req_data
+    } else if data.len() < total {
+        split_batches(
+            &req_data[0],
+            (total - data.len()) as u32,
+            CONFIG.limit.api_max,
+        )
+    } else {
+        Vec::new()
+    };
+    let (more, errors, failed) = fetch(&req_data).await;
+    data.extend(more);
     if data.len() == 0 {
-        // 没有响应的数据。
-        group.send_string(&CONFIG.err_msg.bad_url.clone());
+        if !errors.is_empty() {
+            let mut tmp = HashMap::new();
+            tmp.insert("msg".to_string(), errors.join("；"));
+            // 响应失败。
+            chat.send_string(&strfmt(&CONFIG.err_msg.bad_rsp.clone(), &tmp).unwrap());
+        } else if failed == req_data.len() {
+            // 请求失败。
+            chat.send_string(&CONFIG.err_msg.bad_req.clone());
+        } else {
+            // 没有响应的数据。
+            chat.send_string(&CONFIG.err_msg.bad_url.clone());
+        }
         return;
     }
-    if data.len() < req_data.num.into() {
+    // 多次请求的结果可能重复。
+    let mut pids = HashSet::new();
+    data.retain(|pic_data| pids.insert(pic_data.pid));
+    // 去掉最近在本群发过的图片，并请求新的图片补上。
+    let mut filtered = 0;
+    if !cmd.dup {
+        let chat_id = chat.id();
+        let mut rounds = 0;
+        loop {
+            let len = data.len();
+            data.retain(|pic_data| !seen_recently(chat_id, pic_data.pid, &CONFIG.history));
+            filtered += len - data.len();
+            if data.len() >= total || rounds >= CONFIG.history.max_rounds || req_data.is_empty() {
+                break;
+            }
+            rounds += 1;
+            let batches = split_batches(
+                &req_data[0],
+                (total - data.len()) as u32,
+                CONFIG.limit.api_max,
+            );
+            let (more, _, _) = fetch(&batches).await;
+            let len = data.len();
+            data.extend(
+                more.into_iter()
+                    .filter(|pic_data| pids.insert(pic_data.pid)),
+            );
+            if data.len() == len {
+                break;
+            }
+        }
+        data.truncate(total);
+        if data.len() == 0 {
+            // 没有响应的数据。
+            chat.send_string(&CONFIG.err_msg.bad_url.clone());
+            return;
+        }
+    }
+    if data.len() < total {
         let mut tmp = HashMap::new();
         tmp.insert("n".to_string(), data.len().to_string());
         // 请求的数量小于返回的数量。
-        group.send_string(&strfmt(&CONFIG.err_msg.bad_eql, &tmp).unwrap());
+        chat.send_string(&strfmt(&CONFIG.err_msg.bad_eql, &tmp).unwrap());
     }
 
-    let mut pic_meta_path = std::env::current_dir().unwrap();
-    pic_meta_path.push("pictures");
-    pic_meta_path.push("metadata");
-
-    let map = Mutex::new(HashMap::new());
-
-    let mut jobs = Vec::new();
-    let mut downloads = Vec::new();
-
-    for pic_data in &data {
-        let url = Url::parse(&pic_data.urls.original).unwrap();
-        let filename = url.path_segments().unwrap().last().unwrap().to_string();
-
-        let mut pic_path = std::env::current_dir().unwrap();
-        pic_path.push("pictures");
-        pic_path.push(&filename);
-
-        if fs::metadata(&pic_path).is_err() {
-            downloads.push(Download::new(&url, &filename));
-        } else if rand::thread_rng().gen_range(0..=20) > 3 {
-            downloads.push(Download::new(&url, &filename));
-        }
-        if fs::metadata(&pic_meta_path).is_err() {
-            let _ = fs::create_dir_all(&pic_meta_path);
-        }
-        let job = async {
-            let data_toml = toml::to_string(pic_data).unwrap();
-            let mut path = pic_meta_path.clone();
-            path.push(filename + ".toml");
-            let mut file = tokio::fs::File::create(&path).await.unwrap();
-            file.write_all(data_toml.as_bytes()).await.unwrap();
+    // 合并转发、私发、群文件和拼图只对群聊有意义。
+    let (forward, private, files, collage) = match &chat {
+        Chat::Group(group, _) => (
+            cmd.forward
+                .unwrap_or_else(|| forward_by_default(group.get_id(), &CONFIG.delivery)),
+            cmd.private || private_by_default(group.get_id(), &CONFIG.delivery),
+            cmd.files || (CONFIG.files.threshold > 0 && data.len() >= CONFIG.files.threshold),
+            CONFIG.collage.enabled && cmd.collage && data.len() > 1,
+        ),
+        Chat::Friend(_) => (false, false, false, false),
+    };
+    let (pic_tx, pics) = unbounded();
+    let chat = Arc::new(chat);
+    let deadline = ticket.received + Duration::from_secs(CONFIG.tip_msg.slow_secs);
+    let _ = lq_tx.unbounded_send(Delivery {
+        chat: chat.clone(),
+        forward,
+        private,
+        files,
+        collage,
+        pics,
+        filtered,
+        ticket,
+    });
+    // 已经下载成功的图片数，按完成的先后计数，不受送出顺序影响。
+    let downloaded = AtomicUsize::new(0);
+    let downloaded = &downloaded;
+    // 并发下载，但按 api 返回的顺序送去发送，每张图片准备好（且前面的都已送出）就立即送出。
+    let mut jobs = data
+        .iter_mut()
+        .map(|pic_data| async move {
+            let (pic_path, candidates, ok) = download_pic(pic_data).await;
+            if ok {
+                downloaded.fetch_add(1, Ordering::Relaxed);
+            }
             let tip_doc = {
                 let mut tip_doc = HashMap::new();
                 tip_doc.insert("title".to_string(), pic_data.title.clone());
@@ -330,16 +661,202 @@ pub(crate) async fn task(
                 });
                 tip_doc
             };
-            let msgs_m = At::new(member.get_id()).plus(PlainText::from(
-                strfmt(&CONFIG.tip_msg.tip_doc, &tip_doc).unwrap(),
-            ));
-            map.lock().await.insert(pic_path, msgs_m);
+            let doc = strfmt(&CONFIG.tip_msg.tip_doc, &tip_doc).unwrap();
+            let credit = if CONFIG.watermark.enabled {
+                strfmt(&CONFIG.watermark.text, &tip_doc).unwrap_or_default()
+            } else {
+                String::new()
+            };
+            // 水印要在转换前画上，否则重新编码会抵消压缩。
+            let pic_path = apply_watermark(&pic_path, &credit, &CONFIG.watermark).await;
+            let pic_path = prepare_upload(&pic_path, &CONFIG.transform).await;
+            (
+                pic_path,
+                PicMsg {
+                    pid: pic_data.pid,
+                    p: pic_data.p,
+                    dims: (pic_data.width, pic_data.height),
+                    sensitive: pic_data.r18
+                        || pic_data
+                            .tags
+                            .iter()
+                            .any(|tag| CONFIG.preview.sensitive_tags.contains(tag)),
+                    doc,
+                    credit,
+                    fallbacks: candidates,
+                },
+            )
+        })
+        .collect::<FuturesOrdered<_>>();
+    // 收到指令后超过 slow_secs 秒还没下载完时，提示一次进度。
+    let total = jobs.len();
+    let mut notified = CONFIG.tip_msg.slow_secs == 0;
+    loop {
+        let next = if notified {
+            jobs.next().await
+        } else {
+            match tokio::time::timeout_at(deadline.into(), jobs.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    notified = true;
+                    let mut tmp = HashMap::new();
+                    tmp.insert(
+                        "x".to_string(),
+                        downloaded.load(Ordering::Relaxed).to_string(),
+                    );
+                    tmp.insert("y".to_string(), total.to_string());
+                    chat.send_string(&strfmt(&CONFIG.tip_msg.tip_slow, &tmp).unwrap());
+                    continue;
+                }
+            }
         };
-        jobs.push(job);
+        let Some(pic) = next else {
+            break;
+        };
+        let _ = pic_tx.unbounded_send(pic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pic_data(width: i64, height: i64, sizes: &[&str]) -> PicData {
+        let mut urls = PixUrl::default();
+        for &size in sizes {
+            let url = format!("https://i.pximg.net/{}/12345678_p0.jpg", size);
+            match size {
+                "original" => urls.original = url,
+                "regular" => urls.regular = url,
+                "small" => urls.small = url,
+                "thumb" => urls.thumb = url,
+                "mini" => urls.mini = url,
+                _ => unreachable!(),
+            }
+        }
+        PicData {
+            pid: 12345678,
+            p: 0,
+            uid: 1,
+            title: String::new(),
+            author: String::new(),
+            r18: false,
+            width,
+            height,
+            tags: Vec::new(),
+            ext: "jpg".to_string(),
+            aiType: 0,
+            uploadDate: 0,
+            urls,
+            mirror: String::new(),
+        }
+    }
+
+    fn sizes(candidates: &[(&'static str, Url)]) -> Vec<&'static str> {
+        candidates.iter().map(|(size, _)| *size).collect()
+    }
+
+    #[test]
+    fn no_limit_keeps_every_size_largest_first() {
+        let data = pic_data(2000, 1000, &PIC_SIZES);
+        assert_eq!(sizes(&size_candidates(&data, 0)), PIC_SIZES);
+    }
+
+    #[test]
+    fn sizes_over_the_limit_are_skipped() {
+        let data = pic_data(2000, 1000, &PIC_SIZES);
+        // regular 缩放到 1200x600, 正好等于上限。
+        let candidates = size_candidates(&data, 1200 * 600);
+        assert_eq!(sizes(&candidates), ["regular", "small", "thumb", "mini"]);
+        let candidates = size_candidates(&data, 1200 * 600 - 1);
+        assert_eq!(sizes(&candidates), ["small", "thumb", "mini"]);
+    }
+
+    #[test]
+    fn smallest_size_is_always_kept() {
+        let data = pic_data(2000, 1000, &PIC_SIZES);
+        assert_eq!(sizes(&size_candidates(&data, 1)), ["mini"]);
+        let data = pic_data(2000, 1000, &["original", "regular"]);
+        assert_eq!(sizes(&size_candidates(&data, 1)), ["regular"]);
+    }
+
+    #[test]
+    fn missing_sizes_are_skipped() {
+        let data = pic_data(2000, 1000, &["original", "small"]);
+        assert_eq!(sizes(&size_candidates(&data, 0)), ["original", "small"]);
+        let data = pic_data(2000, 1000, &[]);
+        assert!(size_candidates(&data, 0).is_empty());
+    }
+
+    #[test]
+    fn small_images_are_not_scaled_up() {
+        // 比 regular 的最长边还小的图片，regular 与原图的像素数相同。
+        let data = pic_data(600, 400, &PIC_SIZES);
+        let candidates = size_candidates(&data, 600 * 400);
+        assert_eq!(sizes(&candidates), PIC_SIZES);
+        let candidates = size_candidates(&data, 600 * 400 - 1);
+        assert_eq!(sizes(&candidates), ["small", "thumb", "mini"]);
+    }
+
+    #[test]
+    fn missing_dimensions_do_not_divide_by_zero() {
+        let data = pic_data(0, 0, &PIC_SIZES);
+        assert_eq!(
+            sizes(&size_candidates(&data, 1)),
+            ["original", "regular", "small", "thumb", "mini"]
+        );
+    }
+
+    fn req_data() -> ReqData {
+        ReqData {
+            r18: 0,
+            num: 0,
+            uid: Vec::new(),
+            keyword: "風景".to_string(),
+            tag: vec!["白".to_string()],
+            size: vec!["original".to_string()],
+            proxy: String::new(),
+            dateAfter: 0,
+            dateBefore: 0,
+            dsc: false,
+            excludeAI: true,
+        }
+    }
+
+    fn nums(batches: &[ReqData]) -> Vec<u8> {
+        batches.iter().map(|batch| batch.num).collect()
+    }
+
+    #[test]
+    fn batches_fill_up_to_api_max_with_a_remainder() {
+        assert_eq!(nums(&split_batches(&req_data(), 45, 20)), [20, 20, 5]);
+        assert_eq!(nums(&split_batches(&req_data(), 40, 20)), [20, 20]);
+        assert_eq!(nums(&split_batches(&req_data(), 7, 20)), [7]);
+    }
+
+    #[test]
+    fn zero_api_max_is_treated_as_one() {
+        assert_eq!(nums(&split_batches(&req_data(), 3, 0)), [1, 1, 1]);
+    }
+
+    #[test]
+    fn nothing_requested_means_no_batches() {
+        assert!(split_batches(&req_data(), 0, 20).is_empty());
+    }
+
+    #[test]
+    fn totals_beyond_u8_are_split() {
+        let batches = split_batches(&req_data(), 600, u8::MAX);
+        assert_eq!(nums(&batches), [255, 255, 90]);
+    }
+
+    #[test]
+    fn batches_keep_the_other_fields() {
+        for batch in split_batches(&req_data(), 45, 20) {
+            assert_eq!(batch.keyword, "風景");
+            assert_eq!(batch.tag, ["白"]);
+            assert_eq!(batch.size, ["original"]);
+            assert!(batch.excludeAI);
+        }
     }
-    // for tmp in &downloads {
-    //     println!("下载内容：{:?}", tmp);
-    // }
-    join!(join_all(jobs), downloader.download(&downloads));
-    let _ = lq_tx.unbounded_send((group, member, map));
 }
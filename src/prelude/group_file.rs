@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+};
+
+use strfmt::strfmt;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use super::structs::{FileConfig, PicMsg};
+use crate::prelude::*;
+
+// 群文件中的文件名，形如 `12345678_p0.jpg`.
+fn file_name_of(path: &Path, pic_msg: &PicMsg) -> String {
+    let ext = path
+        .extension()
+        .map_or("jpg".to_string(), |ext| ext.to_string_lossy().to_string());
+    format!("{}_p{}.{}", pic_msg.pid, pic_msg.p, ext)
+}
+
+// 图片已经压缩过，打包时只存储不压缩。
+fn write_zip(path: &Path, pics: &[(String, PathBuf)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = ZipWriter::new(fs::File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, pic_path) in pics {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(&fs::read(pic_path)?)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+// 上传一个文件到群文件夹，失败时返回 false.
+fn upload_file(folder: &AbsoluteFolder, name: &str, path: &Path) -> bool {
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let resource = ExternalResource::create_from_file(path);
+        folder.upload_new_file(name, &resource);
+        resource.close();
+    }))
+    .is_ok()
+}
+
+// 将图片（或打包成的 zip 文件）上传到群文件中配置的文件夹，并在群里发送一条通知。
+// 返回上传成功的 pid, 一张都没有上传成功时返回 None.
+pub(crate) async fn upload_to_group_files(
+    group: &Group,
+    member: &Member,
+    pics: &[(PathBuf, PicMsg)],
+    config: &FileConfig,
+) -> Option<Vec<i64>> {
+    let pics = pics
+        .iter()
+        .filter(|(path, _)| fs::metadata(path).is_ok())
+        .map(|(path, pic_msg)| (pic_msg.pid, file_name_of(path, pic_msg), path.clone()))
+        .collect::<Vec<_>>();
+    if pics.is_empty() {
+        return None;
+    }
+    let folder = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        group.get_files().get_root().create_folder(&config.folder)
+    }))
+    .map_err(|_| eprintln!("无法打开群文件夹 “{}”。", config.folder))
+    .ok()?;
+    let mut uploaded = Vec::new();
+    let mut zip_name = String::new();
+    if config.zip {
+        let secs = now();
+        zip_name = format!("{}.zip", secs);
+        let mut zip_path = std::env::current_dir().unwrap();
+        zip_path.push("pictures");
+        zip_path.push(&zip_name);
+        let entries = pics
+            .iter()
+            .map(|(_, name, path)| (name.clone(), path.clone()))
+            .collect::<Vec<_>>();
+        let written = {
+            let zip_path = zip_path.clone();
+            tokio::task::spawn_blocking(move || {
+                write_zip(&zip_path, &entries).map_err(|err| err.to_string())
+            })
+            .await
+        };
+        match written {
+            Ok(Ok(())) => {
+                if upload_file(&folder, &zip_name, &zip_path) {
+                    uploaded.extend(pics.iter().map(|(pid, _, _)| *pid));
+                }
+            }
+            Ok(Err(err)) => eprintln!("无法打包图片：{}", err),
+            Err(err) => eprintln!("无法打包图片：{}", err),
+        }
+        let _ = fs::remove_file(&zip_path);
+    } else {
+        for (pid, name, path) in &pics {
+            if upload_file(&folder, name, path) {
+                uploaded.push(*pid);
+            } else {
+                eprintln!("无法上传 {} 到群文件。", path.display());
+            }
+        }
+    }
+    if uploaded.is_empty() {
+        return None;
+    }
+    let mut tmp = HashMap::new();
+    tmp.insert("n".to_string(), uploaded.len().to_string());
+    tmp.insert("folder".to_string(), config.folder.clone());
+    tmp.insert("name".to_string(), zip_name);
+    let notice = strfmt(&config.tip_files, &tmp).unwrap();
+    try_send(
+        group,
+        &At::new(member.get_id()).plus(PlainText::from(notice)),
+    );
+    Some(uploaded)
+}
@@ -0,0 +1,19 @@
+use super::{now, record_sends, sent_since, structs::HistoryConfig};
+
+pub(crate) fn seen_recently(group_id: i64, pid: i64, config: &HistoryConfig) -> bool {
+    if config.window_hours == 0 {
+        return false;
+    }
+    let since = now() - config.window_hours as i64 * 3600;
+    sent_since(group_id, pid, since).unwrap_or_else(|err| {
+        eprintln!("无法查询发送记录：{}", err);
+        false
+    })
+}
+
+// 记录发送过的图片。
+pub(crate) fn mark_seen(group_id: i64, pids: &[i64]) {
+    if let Err(err) = record_sends(group_id, pids, now()) {
+        eprintln!("无法保存发送记录：{}", err);
+    }
+}
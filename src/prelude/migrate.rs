@@ -0,0 +1,77 @@
+use std::{fs, path::Path};
+
+use regex::Regex;
+
+use super::{meta_path_of, pic_path_of, relocate_cached, structs::PIC_SIZES};
+
+// 将旧的存储布局迁移为按 pid 分目录的布局，只需要运行一次：
+//      pictures/12345678_p0.png                    -> pictures/12/12345678_p0.original.png
+//      pictures/regular/12345678_p0_master1200.jpg -> pictures/12/12345678_p0.regular.jpg
+//      pictures/metadata/12345678_p0.png.toml      -> pictures/metadata/12345678_p0.toml
+// 旧布局下的变换结果和预览图会被删除，需要时会重新生成。
+pub(crate) fn migrate_library() {
+    let mut root = std::env::current_dir().unwrap();
+    root.push("pictures");
+    let name_rx = Regex::new(r"^(\d+)_p(\d+)[^.]*\.([A-Za-z0-9]+)$").unwrap();
+    let mut moved = 0;
+    let mut removed = 0;
+    let mut dirs = vec![(root.clone(), "original")];
+    dirs.extend(
+        PIC_SIZES
+            .iter()
+            .filter(|&&size| size != "original")
+            .map(|&size| (root.join(size), size)),
+    );
+    for (dir, size) in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(caps) = name_rx.captures(&name) else {
+                // 变换结果（`12345678_p0.1a2b3c4d.jpg`）和预览图的文件名中有多个点。
+                if name.contains("_p") && name.matches('.').count() > 1 {
+                    let _ = fs::remove_file(&path);
+                    removed += 1;
+                }
+                continue;
+            };
+            let (Ok(pid), Ok(p)) = (caps[1].parse::<i64>(), caps[2].parse::<i64>()) else {
+                continue;
+            };
+            let target = pic_path_of(pid, p, size, &caps[3]);
+            if let Err(err) = move_file(&path, &target) {
+                eprintln!("无法移动 {}：{}", path.display(), err);
+                continue;
+            }
+            relocate_cached(&path, &target);
+            moved += 1;
+            if size == "original" {
+                let old_meta = root.join("metadata").join(format!("{}.toml", name));
+                if fs::metadata(&old_meta).is_ok()
+                    && let Err(err) = move_file(&old_meta, &meta_path_of(pid, p))
+                {
+                    eprintln!("无法移动 {}：{}", old_meta.display(), err);
+                }
+            }
+        }
+        if size != "original" {
+            let _ = fs::remove_dir(&dir);
+        }
+    }
+    println!(
+        "迁移完成：移动了 {} 个文件，删除了 {} 个可以重新生成的文件。",
+        moved, removed
+    );
+}
+
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+}
@@ -0,0 +1,198 @@
+use std::{
+    error::Error,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use reqwest::{header::CONTENT_LENGTH, Client};
+use url::Url;
+
+use super::{
+    structs::{Downloaded, MirrorConfig},
+    DOWNLOADER,
+};
+
+lazy_static! {
+    // 上一次测速的时间和按速度排好序的镜像。
+    static ref RANKING: Mutex<Option<(Instant, Vec<String>)>> = Mutex::new(None);
+}
+
+// 将 `i.pximg.net` 一类的图片地址改写为镜像地址。
+// 镜像可以只写主机名（沿用原地址的协议），也可以写成 `https://i.pixiv.re` 的形式。
+pub(crate) fn mirror_url(url: &Url, mirror: &str) -> Result<Url, Box<dyn Error>> {
+    let mut url = url.clone();
+    if mirror.contains("://") {
+        let base = Url::parse(mirror)?;
+        url.set_scheme(base.scheme())
+            .map_err(|_| "无法设置镜像的协议")?;
+        url.set_host(base.host_str())?;
+        url.set_port(base.port())
+            .map_err(|_| "无法设置镜像的端口")?;
+    } else {
+        url.set_host(Some(mirror))?;
+    }
+    Ok(url)
+}
+
+async fn rank_mirrors(client: &Client, url: &Url, hosts: &[String]) -> Vec<String> {
+    let probes = hosts.iter().map(|mirror| async move {
+        let begin = Instant::now();
+        let ok = match mirror_url(url, mirror) {
+            Ok(url) => client
+                .head(url)
+                .send()
+                .await
+                .is_ok_and(|resp| resp.status().is_success()),
+            Err(_) => false,
+        };
+        (!ok, begin.elapsed(), mirror.clone())
+    });
+    let mut probes = join_all(probes).await;
+    // 可用的排在前面，再按耗时排序。
+    probes.sort();
+    probes.into_iter().map(|(_, _, mirror)| mirror).collect()
+}
+
+// 按配置的顺序或测速的结果给出候选的镜像和地址，原地址总是作为最后的退路。
+pub(crate) async fn mirror_candidates(
+    client: &Client,
+    url: &Url,
+    config: &MirrorConfig,
+) -> Vec<(String, Url)> {
+    let mut candidates = Vec::new();
+    if url
+        .host_str()
+        .is_some_and(|host| host.ends_with("pximg.net"))
+    {
+        let hosts = if config.strategy == "fastest" {
+            let cached = RANKING.lock().unwrap().clone();
+            match cached {
+                Some((probed_at, hosts))
+                    if probed_at.elapsed() < Duration::from_secs(config.probe_ttl) =>
+                {
+                    hosts
+                }
+                _ => {
+                    let hosts = rank_mirrors(client, url, &config.hosts).await;
+                    *RANKING.lock().unwrap() = Some((Instant::now(), hosts.clone()));
+                    hosts
+                }
+            }
+        } else {
+            config.hosts.clone()
+        };
+        for mirror in hosts {
+            match mirror_url(url, &mirror) {
+                Ok(url) => candidates.push((mirror, url)),
+                Err(err) => eprintln!("镜像 {} 无效：{}", mirror, err),
+            }
+        }
+    }
+    if let Some(host) = url.host_str()
+        && !candidates.iter().any(|(_, candidate)| candidate == url)
+    {
+        candidates.push((host.to_string(), url.clone()));
+    }
+    candidates
+}
+
+// 通过 HEAD 请求获取远端文件的大小。
+pub(crate) async fn remote_size(client: &Client, url: &Url, config: &MirrorConfig) -> Option<u64> {
+    for (_, url) in mirror_candidates(client, url, config).await {
+        if let Ok(resp) = client.head(url).send().await
+            && resp.status().is_success()
+        {
+            // HEAD 响应没有响应体，`content_length()` 总是返回 0, 所以直接读取响应头。
+            return resp
+                .headers()
+                .get(CONTENT_LENGTH)?
+                .to_str()
+                .ok()?
+                .parse()
+                .ok();
+        }
+    }
+    None
+}
+
+// 依次尝试各个候选地址（跳过 exclude 中的镜像），返回成功下载时使用的镜像和下载结果。
+pub(crate) async fn download_from_mirrors(
+    client: &Client,
+    url: &Url,
+    path: &Path,
+    config: &MirrorConfig,
+    validators: Option<(&str, &str)>,
+    exclude: &[String],
+) -> Result<(String, Option<Downloaded>), Box<dyn Error>> {
+    let mut last_err: Box<dyn Error> = "没有可用的下载地址。".into();
+    for (mirror, url) in mirror_candidates(client, url, config).await {
+        if exclude.contains(&mirror) {
+            continue;
+        }
+        match DOWNLOADER.download(client, &url, path, validators).await {
+            Ok(downloaded) => return Ok((mirror, downloaded)),
+            Err(err) => {
+                eprintln!("从镜像 {} 下载失败：{}", mirror, err);
+                last_err = err;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pximg(path: &str) -> Url {
+        Url::parse(&format!("https://i.pximg.net{}", path)).unwrap()
+    }
+
+    #[test]
+    fn host_only_keeps_scheme_path_and_query() {
+        let url = pximg("/img-original/img/2024/01/01/00/00/00/12345678_p0.png?v=1");
+        let mirrored = mirror_url(&url, "i.pixiv.re").unwrap();
+        assert_eq!(
+            mirrored.as_str(),
+            "https://i.pixiv.re/img-original/img/2024/01/01/00/00/00/12345678_p0.png?v=1"
+        );
+    }
+
+    #[test]
+    fn host_only_keeps_port() {
+        let url = Url::parse("https://i.pximg.net:8443/a.jpg").unwrap();
+        let mirrored = mirror_url(&url, "i.pixiv.re").unwrap();
+        assert_eq!(mirrored.as_str(), "https://i.pixiv.re:8443/a.jpg");
+    }
+
+    #[test]
+    fn full_mirror_replaces_scheme_and_port() {
+        let url = pximg("/a.jpg");
+        let mirrored = mirror_url(&url, "http://127.0.0.1:8080").unwrap();
+        assert_eq!(mirrored.as_str(), "http://127.0.0.1:8080/a.jpg");
+    }
+
+    #[test]
+    fn full_mirror_without_port_clears_port() {
+        let url = Url::parse("https://i.pximg.net:8443/a.jpg").unwrap();
+        let mirrored = mirror_url(&url, "https://i.pixiv.re").unwrap();
+        assert_eq!(mirrored.as_str(), "https://i.pixiv.re/a.jpg");
+    }
+
+    #[test]
+    fn default_port_of_mirror_scheme_is_dropped() {
+        let url = pximg("/a.jpg");
+        let mirrored = mirror_url(&url, "http://i.pixiv.re:80").unwrap();
+        assert_eq!(mirrored.as_str(), "http://i.pixiv.re/a.jpg");
+    }
+
+    #[test]
+    fn invalid_mirror_is_an_error() {
+        let url = pximg("/a.jpg");
+        assert!(mirror_url(&url, "https://").is_err());
+        assert!(mirror_url(&url, "bad host").is_err());
+    }
+}
@@ -1,6 +1,48 @@
 pub(crate) mod prelude;
+pub(crate) use admin::*;
+pub(crate) mod admin;
+pub(crate) use cache::*;
+pub(crate) mod cache;
+pub(crate) use collage::*;
+pub(crate) mod collage;
+pub(crate) use db::*;
+pub(crate) mod db;
+pub(crate) use downloader::*;
+pub(crate) mod downloader;
+pub(crate) use fallback::*;
+pub(crate) mod fallback;
+pub(crate) use font::*;
+pub(crate) mod font;
+pub(crate) use friend::*;
+pub(crate) mod friend;
 pub(crate) use func::*;
 pub(crate) use prelude::*;
 pub(crate) mod func;
+pub(crate) use group_file::*;
+pub(crate) mod group_file;
+pub(crate) use history::*;
+pub(crate) mod history;
+pub(crate) use migrate::*;
+pub(crate) mod migrate;
+pub(crate) use mirror::*;
+pub(crate) mod mirror;
+pub(crate) use prefetch::*;
+pub(crate) mod prefetch;
+pub(crate) use preview::*;
+pub(crate) mod preview;
+pub(crate) use queue::*;
+pub(crate) mod queue;
+pub(crate) use recall::*;
+pub(crate) mod recall;
+pub(crate) use repair::*;
+pub(crate) mod repair;
+pub(crate) use send::*;
+pub(crate) mod send;
 pub(crate) use structs::*;
 pub(crate) mod structs;
+pub(crate) use transform::*;
+pub(crate) mod transform;
+pub(crate) use validate::*;
+pub(crate) mod validate;
+pub(crate) use watermark::*;
+pub(crate) mod watermark;
@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use futures::future::join_all;
+use lazy_static::lazy_static;
+
+use super::{
+    download_pic, fetch, now, seen_recently, split_batches,
+    structs::{Cmd, PicData, PrefetchConfig, PIC_SIZES},
+};
+use crate::CONFIG;
+
+lazy_static! {
+    // 标签（不带标签的池为空字符串） -> 已经下载好的图片。
+    static ref POOLS: Mutex<HashMap<String, VecDeque<PicData>>> = Mutex::new(HashMap::new());
+}
+
+// 最近一条指令的时间戳。
+static LAST_CMD: AtomicU64 = AtomicU64::new(0);
+
+// 指令可以使用的池。
+fn pool_of(cmd: &Cmd, config: &PrefetchConfig) -> Option<String> {
+    if !config.enabled || cmd.r18 != 0 || !cmd.ai {
+        return None;
+    }
+    match cmd.tags.as_slice() {
+        [] => Some(String::new()),
+        [tag] if config.tags.contains(tag) => Some(tag.clone()),
+        _ => None,
+    }
+}
+
+// 记录一条指令，并从匹配的池中取出最多 n 张图片。
+// 最近在本群发过的图片直接丢弃，不再放回池中。
+pub(crate) fn take_prefetched(
+    cmd: &Cmd,
+    group_id: i64,
+    n: usize,
+    config: &PrefetchConfig,
+) -> Vec<PicData> {
+    LAST_CMD.store(now() as u64, Ordering::Relaxed);
+    let mut taken = Vec::new();
+    let Some(key) = pool_of(cmd, config) else {
+        return taken;
+    };
+    let mut pools = POOLS.lock().unwrap();
+    let Some(pool) = pools.get_mut(&key) else {
+        return taken;
+    };
+    while taken.len() < n
+        && let Some(pic_data) = pool.pop_front()
+    {
+        if cmd.dup || !seen_recently(group_id, pic_data.pid, &CONFIG.history) {
+            taken.push(pic_data);
+        }
+    }
+    taken
+}
+
+// 补充各个池，只在最近一段时间没有指令时进行，以免和指令争抢 api 与带宽。
+pub(crate) async fn refill_pools(config: &PrefetchConfig) {
+    if !config.enabled
+        || (now() as u64).saturating_sub(LAST_CMD.load(Ordering::Relaxed)) < config.idle_secs
+    {
+        return;
+    }
+    let mut pools = vec![(String::new(), config.size)];
+    pools.extend(config.tags.iter().map(|tag| (tag.clone(), config.tag_size)));
+    for (tag, size) in pools {
+        let len = POOLS.lock().unwrap().get(&tag).map_or(0, VecDeque::len);
+        if len >= size {
+            continue;
+        }
+        let mut req_data = CONFIG.default_req.clone();
+        req_data.r18 = 0;
+        req_data.excludeAI = true;
+        if req_data.size.is_empty() {
+            req_data.size = PIC_SIZES.iter().map(|size| size.to_string()).collect();
+        }
+        req_data.tag = if tag.is_empty() {
+            Vec::new()
+        } else {
+            vec![tag.clone()]
+        };
+        let batches = split_batches(&req_data, (size - len) as u32, CONFIG.limit.api_max);
+        let (data, errors, _) = fetch(&batches).await;
+        for err in errors {
+            eprintln!("预取 “{}” 失败：{}", tag, err);
+        }
+        let ready = join_all(data.into_iter().map(|mut pic_data| async move {
+            let (_, _, downloaded) = download_pic(&mut pic_data).await;
+            downloaded.then_some(pic_data)
+        }))
+        .await;
+        let mut pools = POOLS.lock().unwrap();
+        let pool = pools.entry(tag).or_default();
+        for pic_data in ready.into_iter().flatten() {
+            if pool.len() < size && !pool.iter().any(|pooled| pooled.pid == pic_data.pid) {
+                pool.push_back(pic_data);
+            }
+        }
+    }
+}
@@ -1,16 +1,21 @@
 pub(crate) use mirai_j4rs::{
     auth::bot_authorization::BotAuthorization,
-    contact::{ContactOrBotTrait, ContactTrait, Group, Member, SendMessageSupportedTrait},
+    contact::{
+        file::AbsoluteFolder, ContactOrBotTrait, ContactTrait, Friend, Group, Member,
+        SendMessageSupportedTrait,
+    },
     event::{FriendMessageEvent, GroupMessageEvent, MessageEventTrait},
     message::{
         data::{
-            At, Audio, MarketFaceAll, MessageChain, PlainText, RockPaperScissors, SingleMessage,
+            At, Audio, ForwardMessageBuilder, Image, MarketFaceAll, MessageChain,
+            MessageSourceBuilder, MessageSourceKind, PlainText, RockPaperScissors, SingleMessage,
         },
-        MarketFaceTrait, MessageTrait,
+        MarketFaceTrait, MessageReceipt, MessageTrait,
     },
     mj_base::env::GetInstanceTrait,
     utils::{
-        bot_builder::BotBuilder, contact::file::AbsoluteFileFolderTrait,
-        other::enums::MiraiProtocol,
+        bot_builder::BotBuilder,
+        contact::file::AbsoluteFileFolderTrait,
+        other::{enums::MiraiProtocol, ExternalResource},
     },
 };
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use image::imageops::FilterType;
+use strfmt::strfmt;
+
+use super::{load_preview, save_preview, structs::PreviewConfig, write_atomic};
+use crate::{prelude::*, CONFIG};
+
+// 预览图与原图放在一起，形如 `12345678_p0.original.preview.jpg`.
+fn preview_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.preview.jpg", stem))
+}
+
+fn render(path: &Path, target: &Path, mode: &str, strength: f32) -> Result<(), String> {
+    let image = image::open(path).map_err(|err| err.to_string())?;
+    // 先缩小再处理，模糊大图太慢了，预览也用不着那么清楚。
+    let image = image.resize(512, 512, FilterType::Triangle);
+    let image = if mode == "pixelate" {
+        let block = strength.max(2.0) as u32;
+        let (w, h) = (image.width(), image.height());
+        image
+            .resize_exact((w / block).max(1), (h / block).max(1), FilterType::Triangle)
+            .resize_exact(w, h, FilterType::Nearest)
+    } else {
+        image.blur(strength)
+    };
+    write_atomic(target, |tmp| {
+        Ok(image
+            .to_rgb8()
+            .save_with_format(tmp, image::ImageFormat::Jpeg)?)
+    })
+    .map_err(|err| err.to_string())
+}
+
+// 生成（或复用已经生成的）模糊或打码后的预览图。
+pub(crate) async fn make_preview(path: &Path, config: &PreviewConfig) -> Option<PathBuf> {
+    let target = preview_path(path);
+    if fs::metadata(&target).is_ok() {
+        return Some(target);
+    }
+    let (src, dst) = (path.to_path_buf(), target.clone());
+    let mode = config.mode.clone();
+    let strength = config.strength;
+    match tokio::task::spawn_blocking(move || render(&src, &dst, &mode, strength)).await {
+        Ok(Ok(())) => Some(target),
+        Ok(Err(err)) => {
+            eprintln!("无法生成 {} 的预览：{}", path.display(), err);
+            None
+        }
+        Err(err) => {
+            eprintln!("无法生成 {} 的预览：{}", path.display(), err);
+            None
+        }
+    }
+}
+
+// 记录预览图对应的原图，返回预览编号。
+pub(crate) fn register_preview(group: i64, pid: i64, path: &Path) -> u64 {
+    match save_preview(group, pid, path, CONFIG.preview.max_entries.max(1)) {
+        Ok(id) => id as u64,
+        Err(err) => {
+            eprintln!("无法保存预览记录：{}", err);
+            0
+        }
+    }
+}
+
+// 处理“原图 <编号>”指令，省略编号时取本群最近的一张预览。不是该指令（或没有开启预览）时返回 false.
+pub(crate) fn handle_original(msg: &str, group: &Group, member: &Member) -> bool {
+    if !CONFIG.preview.enabled {
+        return false;
+    }
+    let Some(arg) = msg.trim().strip_prefix(&CONFIG.preview.cmd) else {
+        return false;
+    };
+    let arg = arg.trim();
+    let id = if arg.is_empty() {
+        None
+    } else if let Ok(id) = arg.parse::<u64>() {
+        Some(id)
+    } else {
+        return false;
+    };
+    let entry = load_preview(group.get_id(), id).unwrap_or_else(|err| {
+        eprintln!("无法读取预览记录：{}", err);
+        None
+    });
+    let mut tmp = HashMap::new();
+    tmp.insert(
+        "id".to_string(),
+        id.map_or(String::new(), |id| id.to_string()),
+    );
+    let Some((pid, path)) = entry.filter(|(_, path)| fs::metadata(path).is_ok()) else {
+        let bad_orig = strfmt(&CONFIG.preview.bad_orig, &tmp).unwrap();
+        try_send(group, &PlainText::from(bad_orig));
+        return true;
+    };
+    tmp.insert("pid".to_string(), pid.to_string());
+    let tip = PlainText::from(strfmt(&CONFIG.preview.tip_orig, &tmp).unwrap());
+    let forward_in_group = || {
+        let image = try_upload(group, &path)?;
+        let time = now() as i32;
+        let forward = ForwardMessageBuilder::new(group)
+            .add(member.get_id(), &CONFIG.preview.cmd, &tip.plus(image), time)
+            .build();
+        try_send(group, &forward)
+    };
+    let sent = if CONFIG.preview.deliver == "forward" {
+        forward_in_group().is_some()
+    } else {
+        // 通过临时会话（或好友）私发，失败时改为在群里合并转发。
+        try_upload(member, &path)
+            .and_then(|image| try_send(member, &tip.plus(image)))
+            .is_some()
+            || forward_in_group().is_some()
+    };
+    if !sent {
+        try_send(group, &PlainText::from(CONFIG.err_msg.bad_dld.as_str()));
+    }
+    true
+}
@@ -0,0 +1,38 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use strfmt::strfmt;
+
+use super::structs::Chat;
+use crate::CONFIG;
+
+// 已经收到但还没有发送完的请求数。
+static QUEUE: AtomicUsize = AtomicUsize::new(0);
+
+// 请求在队列中的凭据，被丢弃（发送完毕或中途放弃）时离开队列。
+pub(crate) struct Ticket {
+    // 收到指令的时间。
+    pub(crate) received: Instant,
+}
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        QUEUE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 请求进入队列，前面还有请求时告诉请求者排在第几位。
+pub(crate) fn enqueue(chat: &Chat) -> Ticket {
+    let ahead = QUEUE.fetch_add(1, Ordering::SeqCst);
+    if ahead > 0 && !CONFIG.tip_msg.tip_queue.is_empty() {
+        let mut tmp = HashMap::new();
+        tmp.insert("n".to_string(), ahead.to_string());
+        tmp.insert("pos".to_string(), (ahead + 1).to_string());
+        chat.send_string(&strfmt(&CONFIG.tip_msg.tip_queue, &tmp).unwrap());
+    }
+    Ticket {
+        received: Instant::now(),
+    }
+}
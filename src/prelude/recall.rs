@@ -0,0 +1,58 @@
+use std::panic::AssertUnwindSafe;
+
+use super::{schedule_recall, structs::RecallConfig, take_due_recalls};
+use crate::{prelude::*, CONFIG};
+
+// 某个群中图片的撤回延时，为零时不撤回。
+pub(crate) fn recall_delay(group_id: i64, sensitive: bool, config: &RecallConfig) -> u64 {
+    let (delay, sensitive_delay) = match config.groups.iter().find(|g| g.group == group_id) {
+        Some(group) => (group.delay, group.sensitive_delay),
+        None => (config.delay, config.sensitive_delay),
+    };
+    if sensitive {
+        sensitive_delay
+    } else {
+        delay
+    }
+}
+
+// 登记一条在 delay 秒后撤回的消息。记录写入数据库，重启后仍会撤回。
+pub(crate) fn recall_later(group_id: i64, receipt: &MessageReceipt<Group>, delay: u64) {
+    if delay == 0 {
+        return;
+    }
+    let source = receipt.get_source();
+    if let Err(err) = schedule_recall(
+        group_id,
+        &source.get_ids(),
+        &source.get_internal_ids(),
+        source.get_time(),
+        now() + delay as i64,
+    ) {
+        eprintln!("无法登记撤回：{}", err);
+    }
+}
+
+// 撤回所有到期的消息。消息源根据记录重建，所以重启前发送的消息也能撤回。
+pub(crate) fn recall_due() {
+    let recalls = match take_due_recalls(now()) {
+        Ok(recalls) => recalls,
+        Err(err) => {
+            eprintln!("无法读取待撤回的消息：{}", err);
+            return;
+        }
+    };
+    for (group_id, ids, internal_ids, time) in recalls {
+        let source = MessageSourceBuilder::new()
+            .ids(&ids)
+            .internal_ids(&internal_ids)
+            .time(time)
+            .sender(CONFIG.bot.bot_id)
+            .target(group_id)
+            .build(CONFIG.bot.bot_id, MessageSourceKind::Group);
+        // 消息可能已经被管理员撤回，或者超过了可以撤回的时间。
+        if std::panic::catch_unwind(AssertUnwindSafe(|| source.recall())).is_err() {
+            eprintln!("撤回群 {} 的消息 {:?} 失败。", group_id, ids);
+        }
+    }
+}
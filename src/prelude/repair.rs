@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use futures::future::join_all;
+use regex::Regex;
+
+use super::{
+    all_pics, cache_key, fetch_cached, forget_cached, import_metadata, load_pic, pic_path_of,
+    quarantine, reindex_cached, size_candidates, validate_image,
+};
+use crate::CONFIG;
+
+// 图库中的一个图片文件。
+struct PicFile {
+    pid: i64,
+    p: i64,
+    size: String,
+    path: PathBuf,
+    bytes: u64,
+}
+
+// 扫描图库，修复图片文件、缓存索引和数据库之间的不一致：
+//      导入 `pictures/metadata` 中数据库还没有的元数据；
+//      删除下载中断留下的 `.part` 文件；
+//      校验图片，没有通过校验的文件会被隔离；
+//      没有元数据的图片是孤儿，delete_orphans 为 true 时删除；
+//      按磁盘上的文件重建缓存索引；
+//      重新下载有元数据但没有任何尺寸的文件的图片。
+pub(crate) async fn repair_library(delete_orphans: bool) {
+    let mut root = std::env::current_dir().unwrap();
+    root.push("pictures");
+    let (imported, import_failed) = import_metadata(true);
+
+    let (files, partial) = scan(&root);
+
+    let mut broken = 0;
+    let mut verified = Vec::new();
+    for file in files {
+        let dims = match load_pic(file.pid, file.p) {
+            Ok(Some(pic_data)) => (pic_data.width, pic_data.height),
+            _ => (0, 0),
+        };
+        // 没有元数据时不知道原图的宽高，只检查文件能否解码。
+        match validate_image(&file.path, &file.size, dims, &CONFIG.validate).await {
+            Ok(()) => verified.push(file),
+            Err(reason) => {
+                eprintln!("{} 没有通过校验：{}", file.path.display(), reason);
+                quarantine(&file.path);
+                broken += 1;
+            }
+        }
+    }
+
+    let known = match all_pics() {
+        Ok(pics) => pics.into_iter().collect::<HashSet<_>>(),
+        Err(err) => {
+            eprintln!("无法读取数据库，不会处理孤儿和缺失的文件：{}", err);
+            println!("修复中止。");
+            return;
+        }
+    };
+    let (orphans, files): (Vec<_>, Vec<_>) = verified
+        .into_iter()
+        .partition(|file| !known.contains(&(file.pid, file.p)));
+    let orphan_pics = orphans
+        .iter()
+        .map(|file| (file.pid, file.p))
+        .collect::<HashSet<_>>();
+    if delete_orphans {
+        for &(pid, p) in &orphan_pics {
+            forget_cached(pid, p);
+        }
+        for file in &orphans {
+            let _ = fs::remove_file(&file.path);
+        }
+    } else {
+        for file in &orphans {
+            println!("孤儿文件：{}", file.path.display());
+        }
+    }
+
+    // 没有删除的孤儿也留在缓存索引中。
+    let indexed = files
+        .iter()
+        .chain(orphans.iter().filter(|_| !delete_orphans))
+        .map(|file| {
+            (
+                cache_key(file.pid, file.p, &file.size),
+                file.path.clone(),
+                file.bytes,
+            )
+        })
+        .collect::<Vec<_>>();
+    let (added, dropped) = reindex_cached(&indexed);
+
+    let present = files
+        .iter()
+        .map(|file| (file.pid, file.p))
+        .collect::<HashSet<_>>();
+    let missing = known
+        .iter()
+        .filter(|pic| !present.contains(pic))
+        .collect::<Vec<_>>();
+    let results = join_all(missing.iter().map(|&&(pid, p)| redownload(pid, p))).await;
+    let redownloaded = results.iter().filter(|&&ok| ok).count();
+
+    println!("修复完成：");
+    println!(
+        "    导入元数据 {} 条，失败 {} 条；",
+        imported, import_failed
+    );
+    println!("    删除未完成的下载 {} 个；", partial);
+    println!("    隔离没有通过校验的文件 {} 个；", broken);
+    println!(
+        "    孤儿图片 {} 张（{} 个文件），{}；",
+        orphan_pics.len(),
+        orphans.len(),
+        if delete_orphans {
+            "已删除"
+        } else {
+            "带上 --delete-orphans 删除"
+        }
+    );
+    println!("    缓存索引新增 {} 条，丢弃 {} 条；", added, dropped);
+    println!(
+        "    缺失的图片 {} 张，重新下载成功 {} 张。",
+        missing.len(),
+        redownloaded
+    );
+}
+
+// 找出分目录中的图片文件，顺便删除 `.part` 文件，返回图片文件和删除的数量。
+// 变换结果和预览图（文件名中有更多的点）不在此列。
+fn scan(root: &Path) -> (Vec<PicFile>, usize) {
+    let name_rx = Regex::new(r"^(\d+)_p(\d+)\.([a-z]+)\.([A-Za-z0-9]+)$").unwrap();
+    let mut files = Vec::new();
+    let mut partial = 0;
+    let Ok(shards) = fs::read_dir(root) else {
+        return (files, partial);
+    };
+    for shard in shards.flatten() {
+        let shard = shard.path();
+        let is_shard = shard
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().parse::<u64>().is_ok());
+        if !is_shard || !shard.is_dir() {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&shard) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".part") {
+                if fs::remove_file(&path).is_ok() {
+                    partial += 1;
+                }
+                continue;
+            }
+            let Some(caps) = name_rx.captures(&name) else {
+                continue;
+            };
+            let (Ok(pid), Ok(p)) = (caps[1].parse(), caps[2].parse()) else {
+                continue;
+            };
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            files.push(PicFile {
+                pid,
+                p,
+                size: caps[3].to_string(),
+                path,
+                bytes: meta.len(),
+            });
+        }
+    }
+    (files, partial)
+}
+
+// 按元数据中的地址重新下载一张图片，优先选择上传限制内最大的尺寸。
+async fn redownload(pid: i64, p: i64) -> bool {
+    let Ok(Some(pic_data)) = load_pic(pid, p) else {
+        return false;
+    };
+    let dims = (pic_data.width, pic_data.height);
+    let mut errors = Vec::new();
+    for (size, url) in size_candidates(&pic_data, CONFIG.upload.max_pixels) {
+        let ext = Path::new(url.path())
+            .extension()
+            .map_or("jpg".to_string(), |ext| ext.to_string_lossy().to_string());
+        let path = pic_path_of(pid, p, size, &ext);
+        match fetch_cached(pid, p, size, dims, &url, &path).await {
+            Ok(_) => return true,
+            Err(err) => errors.push(format!("{}: {}", size, err)),
+        }
+    }
+    eprintln!("无法重新下载 {}_p{}：{:?}", pid, p, errors);
+    false
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use futures::{channel::mpsc::UnboundedReceiver, stream, StreamExt};
+use strfmt::strfmt;
+use url::Url;
+
+use super::structs::{Delivery, DeliveryConfig};
+use crate::{prelude::*, CONFIG};
+
+// 下载（或取出缓存的）一个更小的尺寸，画上水印并变换，返回要上传的文件。
+async fn prepare_smaller(
+    pic_msg: &PicMsg,
+    (size, filepath, url): &(&'static str, PathBuf, Url),
+) -> Option<PathBuf> {
+    if let Err(err) = fetch_cached(pic_msg.pid, pic_msg.p, size, pic_msg.dims, url, filepath).await
+    {
+        eprintln!("下载 {} 失败：{}", url, err);
+        return None;
+    }
+    // 水印要在转换前画上，否则重新编码会抵消压缩。
+    let filepath = apply_watermark(filepath, &pic_msg.credit, &CONFIG.watermark).await;
+    Some(prepare_upload(&filepath, &CONFIG.transform).await)
+}
+
+// 上传一个文件，失败时重试。preview 为 true 时上传的是模糊或打码后的预览图。
+async fn upload_file<C: ContactTrait>(
+    contact: &C,
+    filepath: &Path,
+    pic_msg: &PicMsg,
+    preview: bool,
+) -> Option<Image> {
+    let upload_path = if preview {
+        make_preview(filepath, &CONFIG.preview).await?
+    } else {
+        filepath.to_path_buf()
+    };
+    for attempt in 0..=CONFIG.fallback.retries {
+        if attempt > 0 {
+            log_fallback(contact.get_id(), pic_msg.pid, "重试上传");
+        }
+        if let Some(image) = try_upload(contact, &upload_path) {
+            return Some(image);
+        }
+    }
+    None
+}
+
+// 上传一张图片，失败时依次换用更小的尺寸，返回上传的图片和对应的文件。
+// preview 为 true 时上传的是模糊或打码后的预览图，返回的仍是原图的路径。
+pub(crate) async fn upload_pic<C: ContactTrait>(
+    contact: &C,
+    filepath: &Path,
+    pic_msg: &PicMsg,
+    preview: bool,
+) -> Option<(Image, PathBuf)> {
+    if filepath.metadata().is_ok()
+        && let Some(image) = upload_file(contact, filepath, pic_msg, preview).await
+    {
+        return Some((image, filepath.to_path_buf()));
+    }
+    // 上传失败时依次换用更小的尺寸。
+    for candidate in &pic_msg.fallbacks {
+        log_fallback(contact.get_id(), pic_msg.pid, "更小尺寸");
+        let Some(filepath) = prepare_smaller(pic_msg, candidate).await else {
+            continue;
+        };
+        if let Some(image) = upload_file(contact, &filepath, pic_msg, preview).await {
+            return Some((image, filepath));
+        }
+    }
+    None
+}
+
+// 群默认是否使用合并转发。
+pub(crate) fn forward_by_default(group_id: i64, config: &DeliveryConfig) -> bool {
+    if config.forward_groups.contains(&group_id) {
+        true
+    } else if config.separate_groups.contains(&group_id) {
+        false
+    } else {
+        config.mode == "forward"
+    }
+}
+
+// 群默认是否私发给请求者。
+pub(crate) fn private_by_default(group_id: i64, config: &DeliveryConfig) -> bool {
+    config.private_groups.contains(&group_id) || config.target == "private"
+}
+
+// 上传一张待发送的图片，返回说明和图片组成的消息。
+// 在群里发送的敏感图片上传预览，并附上获取原图的提示；私发的直接上传原图。
+pub(crate) async fn build_content<C: ContactTrait>(
+    contact: &C,
+    group_id: i64,
+    filepath: &Path,
+    pic_msg: &PicMsg,
+    private: bool,
+) -> Option<MessageChain> {
+    let preview = !private && CONFIG.preview.enabled && pic_msg.sensitive;
+    let (image, path) = upload_pic(contact, filepath, pic_msg, preview).await?;
+    Some(compose_content(group_id, pic_msg, image, &path, preview))
+}
+
+// 用一个更小的尺寸重新上传并组成消息，用于消息发送失败（例如图片大小超出限制）时。
+pub(crate) async fn build_smaller_content<C: ContactTrait>(
+    contact: &C,
+    group_id: i64,
+    pic_msg: &PicMsg,
+    private: bool,
+    candidate: &(&'static str, PathBuf, Url),
+) -> Option<MessageChain> {
+    let preview = !private && CONFIG.preview.enabled && pic_msg.sensitive;
+    let filepath = prepare_smaller(pic_msg, candidate).await?;
+    let image = upload_file(contact, &filepath, pic_msg, preview).await?;
+    Some(compose_content(
+        group_id, pic_msg, image, &filepath, preview,
+    ))
+}
+
+// 说明和图片组成的消息，预览图附上获取原图的提示。
+fn compose_content(
+    group_id: i64,
+    pic_msg: &PicMsg,
+    image: Image,
+    path: &Path,
+    preview: bool,
+) -> MessageChain {
+    let content = PlainText::from(pic_msg.doc.clone()).plus(image);
+    if !preview {
+        return content;
+    }
+    let id = register_preview(group_id, pic_msg.pid, path);
+    let mut tmp = HashMap::new();
+    tmp.insert("id".to_string(), id.to_string());
+    tmp.insert("cmd".to_string(), CONFIG.preview.cmd.clone());
+    content.plus(PlainText::from(
+        strfmt(&CONFIG.preview.tip_preview, &tmp).unwrap(),
+    ))
+}
+
+// 发送一批图片，返回发送成功的 pid. 合并转发失败时退回逐条发送。
+pub(crate) async fn deliver(
+    group: &Group,
+    member: &Member,
+    contents: Vec<(&PicMsg, Option<MessageChain>)>,
+    forward: bool,
+) -> Vec<i64> {
+    if forward && contents.len() >= CONFIG.delivery.forward_min {
+        let time = now() as i32;
+        let name = &CONFIG.delivery.sender_name;
+        let mut builder = ForwardMessageBuilder::new(group);
+        for (pic_msg, content) in &contents {
+            builder = match content {
+                Some(content) => builder.add(CONFIG.bot.bot_id, name, content, time),
+                None => {
+                    let bad_msg = format!("{}{}", pic_msg.doc, CONFIG.err_msg.bad_dld);
+                    builder.add(CONFIG.bot.bot_id, name, &PlainText::from(bad_msg), time)
+                }
+            };
+        }
+        if let Some(receipt) = try_send(group, &builder.build()) {
+            // 只要有一张敏感图片，整条转发消息就按敏感图片的延时撤回。
+            let sensitive = contents.iter().any(|(pic_msg, _)| pic_msg.sensitive);
+            let delay = recall_delay(group.get_id(), sensitive, &CONFIG.recall);
+            recall_later(group.get_id(), &receipt, delay);
+            return contents
+                .iter()
+                .filter(|(_, content)| content.is_some())
+                .map(|(pic_msg, _)| pic_msg.pid)
+                .collect();
+        }
+        for (pic_msg, _) in &contents {
+            log_fallback(group.get_id(), pic_msg.pid, "逐条发送");
+        }
+    }
+    let mut sent = Vec::new();
+    for (pic_msg, content) in contents {
+        let outcome = send_with_fallback(
+            group,
+            Some(member.get_id()),
+            group.get_id(),
+            false,
+            pic_msg,
+            content,
+            &CONFIG.fallback,
+        )
+        .await;
+        if let Outcome::Sent(receipt) = outcome {
+            let delay = recall_delay(group.get_id(), pic_msg.sensitive, &CONFIG.recall);
+            recall_later(group.get_id(), &receipt, delay);
+            sent.push(pic_msg.pid);
+        }
+    }
+    sent
+}
+
+// 发送完毕后的总结。
+fn summary(ok: usize, failed: usize, filtered: usize) -> String {
+    let mut tmp = HashMap::new();
+    tmp.insert("ok".to_string(), ok.to_string());
+    tmp.insert("failed".to_string(), failed.to_string());
+    tmp.insert("filtered".to_string(), filtered.to_string());
+    strfmt(&CONFIG.tip_msg.tip_sum, &tmp).unwrap()
+}
+
+// 发送一次指令的结果。逐条发送时每张图片一到就发送，合并转发时等所有图片到齐。
+// 最后发送成功、失败和过滤数量的总结。
+pub(crate) async fn send_delivery(delivery: Delivery) {
+    let Delivery {
+        chat,
+        forward,
+        private,
+        files,
+        collage,
+        pics,
+        filtered,
+        // 发送完毕（函数返回）时才离开队列。
+        ticket: _ticket,
+    } = delivery;
+    let (group, member) = match &*chat {
+        Chat::Group(group, member) => (group, member),
+        Chat::Friend(friend) => return send_to_friend(friend, pics, filtered).await,
+    };
+    let group_id = group.get_id();
+    // 上传到群文件或拼图时等所有图片到齐，都失败则照常发送。
+    let mut pics = if files || collage {
+        let pics = pics.collect::<Vec<_>>().await;
+        let mut done = None;
+        if files {
+            done = upload_to_group_files(group, member, &pics, &CONFIG.files).await;
+        }
+        if collage && done.is_none() {
+            done = send_collage(group, member, &pics, &CONFIG.collage).await;
+        }
+        if let Some(done) = done {
+            mark_seen(group_id, &done);
+            let failed = pics.len() - done.len();
+            try_send(
+                group,
+                &PlainText::from(summary(done.len(), failed, filtered)),
+            );
+            return;
+        }
+        stream::iter(pics).boxed()
+    } else {
+        pics.boxed()
+    };
+    let mut sent = Vec::new();
+    let mut failed = 0;
+    // 私发时每张图片一到就发送，不合并转发（不会刷屏）。
+    // 私发失败（例如无法发起临时会话）时，这张和之后的图片改为在群里发送。
+    let mut sent_privately = 0;
+    let mut pending = None;
+    if private {
+        while let Some((filepath, pic_msg)) = pics.next().await {
+            if let Some(content) = build_content(member, group_id, &filepath, &pic_msg, true).await
+                && try_send(member, &content).is_some()
+            {
+                sent.push(pic_msg.pid);
+                sent_privately += 1;
+            } else {
+                log_fallback(member.get_id(), pic_msg.pid, "改为群发");
+                pending = Some((filepath, pic_msg));
+                break;
+            }
+        }
+    }
+    let fallback = pending.is_some();
+    let mut pics = stream::iter(pending).chain(pics);
+    if !private || fallback {
+        if forward {
+            let pics = pics.collect::<Vec<_>>().await;
+            let mut contents = Vec::new();
+            for (filepath, pic_msg) in &pics {
+                let content = build_content(group, group_id, filepath, pic_msg, false).await;
+                contents.push((pic_msg, content));
+            }
+            let total = contents.len();
+            let delivered = deliver(group, member, contents, true).await;
+            failed = total - delivered.len();
+            sent.extend(delivered);
+        } else {
+            while let Some((filepath, pic_msg)) = pics.next().await {
+                let content = build_content(group, group_id, &filepath, &pic_msg, false).await;
+                let delivered = deliver(group, member, vec![(&pic_msg, content)], false).await;
+                if delivered.is_empty() {
+                    failed += 1;
+                }
+                sent.extend(delivered);
+            }
+        }
+    }
+    mark_seen(group_id, &sent);
+    let summary = summary(sent.len(), failed, filtered);
+    if sent_privately > 0 {
+        // 群里只留一条简短的通知。
+        let mut tmp = HashMap::new();
+        tmp.insert("n".to_string(), sent_privately.to_string());
+        let notice = strfmt(&CONFIG.delivery.tip_private, &tmp).unwrap();
+        try_send(
+            group,
+            &At::new(member.get_id()).plus(PlainText::from(notice)),
+        );
+    }
+    if private && !fallback && try_send(member, &PlainText::from(summary.clone())).is_some() {
+        return;
+    }
+    try_send(group, &PlainText::from(summary));
+}
+
+// 好友私聊中的结果逐条发送，没有预览，也不撤回。
+async fn send_to_friend(
+    friend: &Friend,
+    mut pics: UnboundedReceiver<(PathBuf, PicMsg)>,
+    filtered: usize,
+) {
+    let chat_id = -friend.get_id();
+    let mut sent = Vec::new();
+    let mut failed = 0;
+    while let Some((filepath, pic_msg)) = pics.next().await {
+        let content = build_content(friend, chat_id, &filepath, &pic_msg, true).await;
+        match send_with_fallback(
+            friend,
+            None,
+            chat_id,
+            true,
+            &pic_msg,
+            content,
+            &CONFIG.fallback,
+        )
+        .await
+        {
+            Outcome::Sent(_) => sent.push(pic_msg.pid),
+            Outcome::Linked | Outcome::Failed => failed += 1,
+        }
+    }
+    mark_seen(chat_id, &sent);
+    try_send(
+        friend,
+        &PlainText::from(summary(sent.len(), failed, filtered)),
+    );
+}
@@ -1,4 +1,13 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use futures::channel::mpsc::UnboundedReceiver;
 use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::Ticket;
+use crate::prelude::{
+    ContactOrBotTrait, Friend, Group, Member, MessageReceipt, SendMessageSupportedTrait,
+};
 
 #[derive(Deserialize, Serialize)]
 pub(crate) struct BotInfo {
@@ -14,6 +23,30 @@ pub(crate) struct BotInfo {
 pub(crate) struct PremInfo {
     pub(crate) groups: Vec<i64>,
     pub(crate) members: Vec<i64>,
+    #[serde(default)]
+    pub(crate) admins: Vec<i64>,
+}
+// 数量相关的限制。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct LimitConfig {
+    // api 单次请求允许的最大数量。
+    pub(crate) api_max: u8,
+    // 数据库中图片的总量，超过它的请求会被视为无理取闹。
+    pub(crate) db_total: i128,
+    // 管理员与普通成员单条指令可以请求的最大数量。
+    pub(crate) admin_max: u32,
+    pub(crate) member_max: u32,
+}
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            api_max: 20,
+            db_total: 9_4266,
+            admin_max: 20,
+            member_max: 20,
+        }
+    }
 }
 #[derive(Deserialize, Serialize, Clone, Debug)]
 #[allow(
@@ -33,9 +66,39 @@ pub(crate) struct ReqData {
     pub(crate) dsc: bool,
     pub(crate) excludeAI: bool,
 }
-#[derive(Deserialize, Serialize)]
+// api 可以返回的所有尺寸，从大到小排列。
+pub(crate) const PIC_SIZES: [&str; 5] = ["original", "regular", "small", "thumb", "mini"];
+// api 没有返回的尺寸为空字符串。
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
 pub(crate) struct PixUrl {
     pub(crate) original: String,
+    pub(crate) regular: String,
+    pub(crate) small: String,
+    pub(crate) thumb: String,
+    pub(crate) mini: String,
+}
+impl PixUrl {
+    pub(crate) fn get(&self, size: &str) -> &str {
+        match size {
+            "original" => &self.original,
+            "regular" => &self.regular,
+            "small" => &self.small,
+            "thumb" => &self.thumb,
+            "mini" => &self.mini,
+            _ => "",
+        }
+    }
+    // 各尺寸的最长边，原图为 None.
+    pub(crate) fn max_side(size: &str) -> Option<i64> {
+        match size {
+            "regular" => Some(1200),
+            "small" => Some(540),
+            "thumb" => Some(250),
+            "mini" => Some(48),
+            _ => None,
+        }
+    }
 }
 #[allow(
     non_snake_case,
@@ -56,6 +119,9 @@ pub(crate) struct PicData {
     pub(crate) aiType: i8,
     pub(crate) uploadDate: i64,
     pub(crate) urls: PixUrl,
+    // 下载时实际使用的镜像，不是 api 返回的字段，只记录在元数据中。
+    #[serde(default)]
+    pub(crate) mirror: String,
 }
 #[derive(Deserialize, Serialize)]
 pub(crate) struct RespData {
@@ -79,13 +145,586 @@ pub(crate) struct ErrMsg {
 pub(crate) struct TipMsg {
     pub(crate) tip_cmd: String,
     pub(crate) tip_doc: String,
-    pub(crate) tip_end: String,
+    // 发送完毕后的总结，{ok}、{failed}、{filtered} 分别为发送成功、失败和因最近发过而被过滤的数量。
+    #[serde(default = "default_tip_sum")]
+    pub(crate) tip_sum: String,
+    // 前面还有请求时的提示，{n} 为前面的请求数，{pos} 为排在第几位，为空时不提示。
+    #[serde(default = "default_tip_queue")]
+    pub(crate) tip_queue: String,
+    // 收到指令后超过这么多秒还没下载完时提示一次进度，为零时不提示。
+    #[serde(default = "default_slow_secs")]
+    pub(crate) slow_secs: u64,
+    // {x} 为已下载的图片数，{y} 为总数。
+    #[serde(default = "default_tip_slow")]
+    pub(crate) tip_slow: String,
+}
+fn default_tip_sum() -> String {
+    "发送完毕：成功 {ok} 张，失败 {failed} 张，过滤 {filtered} 张。".to_string()
+}
+fn default_tip_queue() -> String {
+    "前面还有 {n} 个请求，请稍候。".to_string()
+}
+fn default_slow_secs() -> u64 {
+    30
+}
+fn default_tip_slow() -> String {
+    "仍在处理中（已下载 {x}/{y} 张）……".to_string()
 }
 #[derive(Deserialize, Serialize)]
 pub(crate) struct JvmConfig {
     pub(crate) jars: Vec<String>,
     pub(crate) opts: Vec<String>,
 }
+// API 请求与图片下载共用的 HTTP 客户端配置，各项留空或为零时不作设置。
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub(crate) struct HttpConfig {
+    // 形如 `http://127.0.0.1:7890` 或 `socks5://127.0.0.1:7891`.
+    pub(crate) proxy: String,
+    // 单位为秒。
+    pub(crate) connect_timeout: u64,
+    pub(crate) read_timeout: u64,
+    pub(crate) user_agent: String,
+    pub(crate) referer: String,
+    pub(crate) headers: HashMap<String, String>,
+    // 额外信任的 CA 证书，PEM 格式的文件路径。
+    pub(crate) ca_certs: Vec<String>,
+}
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct MirrorConfig {
+    // 用于替换 `i.pximg.net` 的镜像，可以只写主机名。
+    pub(crate) hosts: Vec<String>,
+    // "order" 按顺序尝试，"fastest" 按测速结果尝试。
+    pub(crate) strategy: String,
+    // 测速结果的有效期，单位为秒。
+    pub(crate) probe_ttl: u64,
+}
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            strategy: "order".to_string(),
+            probe_ttl: 600,
+        }
+    }
+}
+// 聊天平台对上传图片的限制，为零时不作限制。
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub(crate) struct UploadConfig {
+    pub(crate) max_bytes: u64,
+    pub(crate) max_pixels: u64,
+}
+// 指令的来源：群聊中的成员，或者私聊的好友。
+pub(crate) enum Chat {
+    Group(Group, Member),
+    Friend(Friend),
+}
+impl Chat {
+    // 会话的编号，发送记录等按会话区分的数据使用。好友私聊使用负的 QQ 号，以免和群号混淆。
+    pub(crate) fn id(&self) -> i64 {
+        match self {
+            Chat::Group(group, _) => group.get_id(),
+            Chat::Friend(friend) => -friend.get_id(),
+        }
+    }
+    pub(crate) fn send_string(&self, s: &str) {
+        match self {
+            Chat::Group(group, _) => {
+                group.send_string(s);
+            }
+            Chat::Friend(friend) => {
+                friend.send_string(s);
+            }
+        }
+    }
+}
+// 一次指令的发送任务。图片按 api 返回的顺序逐张送来，全部送完后通道关闭。
+pub(crate) struct Delivery {
+    // 下载图片的任务也要用来提示进度。
+    pub(crate) chat: Arc<Chat>,
+    // 是否合并转发。
+    pub(crate) forward: bool,
+    // 是否私发给请求者。
+    pub(crate) private: bool,
+    // 是否上传到群文件。
+    pub(crate) files: bool,
+    // 是否拼成一张图发送。
+    pub(crate) collage: bool,
+    pub(crate) pics: UnboundedReceiver<(PathBuf, PicMsg)>,
+    // 因最近发过而被过滤掉的图片数。
+    pub(crate) filtered: usize,
+    // 发送完毕时随之丢弃，离开队列。
+    pub(crate) ticket: Ticket,
+}
+// 一张待发送的图片：不含图片的消息，以及上传失败时依次尝试的更小尺寸。
+pub(crate) struct PicMsg {
+    pub(crate) pid: i64,
+    pub(crate) p: i64,
+    // 原图的宽和高，用于校验。
+    pub(crate) dims: (i64, i64),
+    // r18 或带有敏感标签的图片。
+    pub(crate) sensitive: bool,
+    // 图片的说明，即填好的 tip_doc.
+    pub(crate) doc: String,
+    // 要画在图片上的署名，为空时不画。
+    pub(crate) credit: String,
+    pub(crate) fallbacks: Vec<(&'static str, PathBuf, Url)>,
+}
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct CacheConfig {
+    // 缓存的图片在多少秒内不向服务器重新验证。
+    pub(crate) revalidate_secs: u64,
+    // 缓存的总容量（字节，包括水印、变换和预览等结果）与最长保留天数，为零时不作限制。
+    pub(crate) max_bytes: u64,
+    pub(crate) max_age_days: u64,
+    // 超出容量时的淘汰策略，"lru" 或 "lfu".
+    pub(crate) policy: String,
+    // 两次淘汰之间的间隔，单位为秒。
+    pub(crate) evict_interval: u64,
+    // 永不淘汰的 pid.
+    pub(crate) pinned: Vec<i64>,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            revalidate_secs: 24 * 3600,
+            max_bytes: 0,
+            max_age_days: 0,
+            policy: "lru".to_string(),
+            evict_interval: 3600,
+            pinned: Vec::new(),
+        }
+    }
+}
+#[derive(Default)]
+pub(crate) struct CacheStats {
+    pub(crate) files: usize,
+    pub(crate) pics: usize,
+    pub(crate) pinned: usize,
+    pub(crate) bytes: u64,
+}
+// 缓存中一张图片的记录。
+#[derive(Deserialize, Serialize, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) mirror: String,
+    pub(crate) sha256: String,
+    pub(crate) size: u64,
+    pub(crate) etag: String,
+    pub(crate) last_modified: String,
+    // 上一次下载或验证的时间戳。
+    pub(crate) checked_at: u64,
+    #[serde(default)]
+    pub(crate) last_access: u64,
+    #[serde(default)]
+    pub(crate) hits: u64,
+    // 被管理员固定的图片不会被淘汰。
+    #[serde(default)]
+    pub(crate) pinned: bool,
+    // 是否已经通过了图片校验。
+    #[serde(default)]
+    pub(crate) validated: bool,
+}
+// 所有下载共用的限制。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct DownloadConfig {
+    // 同时进行的下载数，以及同一主机同时进行的下载数。
+    pub(crate) concurrency: usize,
+    pub(crate) per_host: usize,
+    // 总带宽，单位为字节每秒，为零时不限速。
+    pub(crate) bandwidth: u64,
+    pub(crate) retries: u32,
+    // 单个文件的超时，单位为秒，为零时不限制。
+    pub(crate) timeout: u64,
+}
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            per_host: 4,
+            bandwidth: 0,
+            retries: 2,
+            timeout: 120,
+        }
+    }
+}
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct ValidateConfig {
+    pub(crate) enabled: bool,
+    // 缩略图的宽高比与原图相差多少仍视为正常。
+    pub(crate) tolerance: f64,
+}
+impl Default for ValidateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            tolerance: 0.02,
+        }
+    }
+}
+// 上传前对图片做的变换，按顺序执行。
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum TransformStep {
+    // 将 from 中的格式（如 "webp", "avif"）转换为 to ("jpeg" 或 "png").
+    Convert { from: Vec<String>, to: String },
+    // 缩小到最长边不超过 max_dimension.
+    Resize { max_dimension: u32 },
+    // 重新压缩到不超过 target_bytes, 质量最低降到 min_quality.
+    Recompress { target_bytes: u64, min_quality: u8 },
+    StripMetadata,
+}
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub(crate) struct TransformConfig {
+    pub(crate) steps: Vec<TransformStep>,
+}
+// 敏感图片先在群里发送模糊的预览，想看原图的成员再用指令获取。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct PreviewConfig {
+    pub(crate) enabled: bool,
+    // 除 r18 外，带有这些标签的图片也视为敏感。
+    pub(crate) sensitive_tags: Vec<String>,
+    // "blur" 或 "pixelate".
+    pub(crate) mode: String,
+    // 模糊的程度，或者马赛克的块大小。
+    pub(crate) strength: f32,
+    // 获取原图的指令，后接预览编号。
+    pub(crate) cmd: String,
+    // "private" 私发，"forward" 以转发消息的形式发在群里。
+    pub(crate) deliver: String,
+    // 最多记录多少张预览。
+    pub(crate) max_entries: usize,
+    pub(crate) tip_preview: String,
+    pub(crate) tip_orig: String,
+    pub(crate) bad_orig: String,
+}
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sensitive_tags: Vec::new(),
+            mode: "blur".to_string(),
+            strength: 12.0,
+            cmd: "原图".to_string(),
+            deliver: "private".to_string(),
+            max_entries: 500,
+            tip_preview: "预览 #{id}，发送“{cmd} {id}”获取原图。".to_string(),
+            tip_orig: "pid: {pid}".to_string(),
+            bad_orig: "没有找到预览 #{id}。".to_string(),
+        }
+    }
+}
+// 一次完整下载的结果。
+pub(crate) struct Downloaded {
+    pub(crate) sha256: String,
+    pub(crate) size: u64,
+    pub(crate) etag: String,
+    pub(crate) last_modified: String,
+}
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct HistoryConfig {
+    // 多少小时内在同一个群发过的图片不再发送，为零时不作限制。
+    pub(crate) window_hours: u64,
+    // 因重复而不够数时，最多再请求几轮。
+    pub(crate) max_rounds: u32,
+}
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            window_hours: 24,
+            max_rounds: 2,
+        }
+    }
+}
+// 从指令中解析出的参数。
+#[derive(Debug)]
+pub(crate) struct Cmd {
+    pub(crate) num: i128,
+    pub(crate) r18: u8,
+    pub(crate) tags: Vec<String>,
+    pub(crate) ai: bool,
+    // 是否允许发送最近发过的图片。
+    pub(crate) dup: bool,
+    // 指令中指定的发送方式，true 为合并转发，None 时按群的配置。
+    pub(crate) forward: Option<bool>,
+    // 指令中要求私发。
+    pub(crate) private: bool,
+    // 指令中要求上传到群文件。
+    pub(crate) files: bool,
+    // 指令中要求拼图。
+    pub(crate) collage: bool,
+}
+// 管理员指令，只有 `prem.admins` 中的成员可以使用。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct AdminConfig {
+    pub(crate) prefix: String,
+    pub(crate) tip_stat: String,
+    pub(crate) tip_dl: String,
+    pub(crate) tip_pin: String,
+    pub(crate) tip_unpin: String,
+    pub(crate) bad_pin: String,
+    pub(crate) tip_query: String,
+    pub(crate) tip_fallback: String,
+}
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "#".to_string(),
+            tip_stat: "缓存中共有 {pics} 张图片（{files} 个文件），占用 {mib} MiB, 其中 {pinned} 张已固定。".to_string(),
+            tip_dl: "正在进行 {n} 个下载。".to_string(),
+            tip_pin: "已固定 {pid} 的 {n} 个文件。".to_string(),
+            tip_unpin: "已取消固定 {pid} 的 {n} 个文件。".to_string(),
+            bad_pin: "缓存中没有 {pid}.".to_string(),
+            tip_query: "图库中找到 {n} 张图片。".to_string(),
+            tip_fallback: "最近 {hours} 小时用到的退路：".to_string(),
+        }
+    }
+}
+// 多张图片的发送方式。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct DeliveryConfig {
+    // "separate" 每张图片一条消息，"forward" 合并为一条转发消息。
+    pub(crate) mode: String,
+    // 单独指定发送方式的群，优先于 mode.
+    pub(crate) forward_groups: Vec<i64>,
+    pub(crate) separate_groups: Vec<i64>,
+    // 少于这么多张图片时总是逐条发送。
+    pub(crate) forward_min: usize,
+    // 转发消息中每条消息显示的发送者名称。
+    pub(crate) sender_name: String,
+    // "group" 在群里发送，"private" 通过好友或临时会话私发给请求者。
+    pub(crate) target: String,
+    // 总是私发的群，优先于 target.
+    pub(crate) private_groups: Vec<i64>,
+    // 私发后在群里留下的通知，{n} 为私发的数量。
+    pub(crate) tip_private: String,
+}
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            mode: "separate".to_string(),
+            forward_groups: Vec::new(),
+            separate_groups: Vec::new(),
+            forward_min: 2,
+            sender_name: "涩图".to_string(),
+            target: "group".to_string(),
+            private_groups: Vec::new(),
+            tip_private: "已私发 {n} 张图片，请查收。".to_string(),
+        }
+    }
+}
+// 图片较多时上传到群文件，而不是逐张发送。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct FileConfig {
+    // 达到这么多张图片时上传到群文件，为零时只在指令要求时上传。
+    pub(crate) threshold: usize,
+    // 群文件中的文件夹。
+    pub(crate) folder: String,
+    // 是否打包成一个 zip 文件上传。
+    pub(crate) zip: bool,
+    // {n} 为上传的图片数，{folder} 为文件夹，{name} 为 zip 文件名（不打包时为空）。
+    pub(crate) tip_files: String,
+}
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0,
+            folder: "涩图".to_string(),
+            zip: false,
+            tip_files: "已将 {n} 张图片上传到群文件 “{folder}” {name}。".to_string(),
+        }
+    }
+}
+// 把多张图片拼成一张网格图发送。
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub(crate) struct CollageConfig {
+    pub(crate) enabled: bool,
+    // 每行的格数，为零时按图片数自动排成接近正方形。
+    pub(crate) columns: u32,
+    // 每一格的边长（像素）。
+    pub(crate) tile: u32,
+    // 是否在每一格上标注序号和 pid.
+    pub(crate) captions: bool,
+    // 字体文件（ttf/otf），为空时使用内置的字体。
+    pub(crate) font: String,
+    pub(crate) font_size: f32,
+    // 获取拼图中某一格原图的指令。
+    pub(crate) cmd: String,
+    // {id} 为拼图编号，{n} 为图片数，{cmd} 为获取原图的指令。
+    pub(crate) tip_collage: String,
+    // {index} 为请求的序号。
+    pub(crate) bad_tile: String,
+}
+impl Default for CollageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            columns: 0,
+            tile: 400,
+            captions: true,
+            font: String::new(),
+            font_size: 24.0,
+            cmd: "大图".to_string(),
+            tip_collage: "拼图 #{id}，共 {n} 张，发送“{cmd} <序号>”获取原图。".to_string(),
+            bad_tile: "没有找到第 {index} 张图片。".to_string(),
+        }
+    }
+}
+// 上传或发送图片失败时依次尝试的退路：重试、更小的尺寸、合并转发、只发文字和链接。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct FallbackConfig {
+    // 上传或发送失败后重试的次数。
+    pub(crate) retries: u32,
+    // 单独发送失败时是否改为合并转发。
+    pub(crate) forward: bool,
+    // 最后是否只发送说明和作品链接。
+    pub(crate) link: bool,
+    // 可用 {doc} 和 {pid}.
+    pub(crate) tip_link: String,
+}
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            retries: 1,
+            forward: true,
+            link: true,
+            tip_link: "{doc}图片发不出来，请到 https://www.pixiv.net/artworks/{pid} 查看。"
+                .to_string(),
+        }
+    }
+}
+// 一张图片走过退路之后的结果。
+pub(crate) enum Outcome<C> {
+    // 图片已发出（可能是合并转发）。
+    Sent(MessageReceipt<C>),
+    // 只发出了文字：作品链接，或者不用链接时下载失败的提示。
+    Linked,
+    Failed,
+}
+// 署名水印画在图片上的位置。
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WatermarkPosition {
+    // 贯穿底部的一条横栏。
+    Footer,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+// 在发送的图片上画上标题、作者和 pid, 转发到别处时也能看到出处。
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub(crate) struct WatermarkConfig {
+    pub(crate) enabled: bool,
+    // 可用 tip_doc 中的 {title}, {author}, {pid}, {uid} 等。
+    pub(crate) text: String,
+    // 支持中文的字体文件（ttf/otf），为空或无法加载时使用内置的字体（没有中文）。
+    pub(crate) font: String,
+    // 字号占图片短边的比例。
+    pub(crate) font_scale: f32,
+    pub(crate) position: WatermarkPosition,
+    // 水印（底色和文字）的不透明度，0 到 1.
+    pub(crate) opacity: f32,
+}
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            text: "{title} / {author} / pid {pid}".to_string(),
+            font: String::new(),
+            font_scale: 0.03,
+            position: WatermarkPosition::Footer,
+            opacity: 0.6,
+        }
+    }
+}
+// 好友私聊使用的权限和限制。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct FriendConfig {
+    pub(crate) enabled: bool,
+    // 可以使用的好友，为空时所有好友都可以使用。
+    pub(crate) friends: Vec<i64>,
+    // 单条指令可以请求的最大数量。
+    pub(crate) max: u32,
+    // 同一个好友两条指令之间至少间隔的秒数。
+    pub(crate) cooldown: u64,
+    pub(crate) allow_r18: bool,
+    pub(crate) bad_r18: String,
+    // {n} 为还需要等待的秒数。
+    pub(crate) bad_cd: String,
+}
+impl Default for FriendConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            friends: Vec::new(),
+            max: 10,
+            cooldown: 30,
+            allow_r18: false,
+            bad_r18: "私聊中不能请求 r18 图片。".to_string(),
+            bad_cd: "请求太频繁了，{n} 秒后再试吧。".to_string(),
+        }
+    }
+}
+// 发送的图片在一段时间后自动撤回，延时为零时不撤回。
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub(crate) struct RecallConfig {
+    // 单位为秒。
+    pub(crate) delay: u64,
+    // r18 或带有敏感标签的图片使用的延时。
+    pub(crate) sensitive_delay: u64,
+    // 按群覆盖上面两项。
+    pub(crate) groups: Vec<GroupRecall>,
+}
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub(crate) struct GroupRecall {
+    pub(crate) group: i64,
+    pub(crate) delay: u64,
+    pub(crate) sensitive_delay: u64,
+}
+// 预先下载好一些图片，默认参数（非 r18、排除 AI）的指令可以直接从池中取图。
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct PrefetchConfig {
+    pub(crate) enabled: bool,
+    // 不带标签的指令使用的池的容量。
+    pub(crate) size: usize,
+    // 热门标签，每个标签一个池，只带一个标签的指令使用。
+    pub(crate) tags: Vec<String>,
+    pub(crate) tag_size: usize,
+    // 两次补充之间的间隔，以及最近一条指令过去多少秒后才补充，单位为秒。
+    pub(crate) interval: u64,
+    pub(crate) idle_secs: u64,
+}
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: 10,
+            tags: Vec::new(),
+            tag_size: 5,
+            interval: 60,
+            idle_secs: 120,
+        }
+    }
+}
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Config {
     pub(crate) api_url: String,
@@ -96,4 +735,42 @@ pub(crate) struct Config {
     pub(crate) err_msg: ErrMsg,
     pub(crate) tip_msg: TipMsg,
     pub(crate) jvm: JvmConfig,
+    #[serde(default)]
+    pub(crate) http: HttpConfig,
+    #[serde(default)]
+    pub(crate) mirror: MirrorConfig,
+    #[serde(default)]
+    pub(crate) upload: UploadConfig,
+    #[serde(default)]
+    pub(crate) limit: LimitConfig,
+    #[serde(default)]
+    pub(crate) history: HistoryConfig,
+    #[serde(default)]
+    pub(crate) cache: CacheConfig,
+    #[serde(default)]
+    pub(crate) admin: AdminConfig,
+    #[serde(default)]
+    pub(crate) download: DownloadConfig,
+    #[serde(default)]
+    pub(crate) validate: ValidateConfig,
+    #[serde(default)]
+    pub(crate) transform: TransformConfig,
+    #[serde(default)]
+    pub(crate) preview: PreviewConfig,
+    #[serde(default)]
+    pub(crate) prefetch: PrefetchConfig,
+    #[serde(default)]
+    pub(crate) delivery: DeliveryConfig,
+    #[serde(default)]
+    pub(crate) recall: RecallConfig,
+    #[serde(default)]
+    pub(crate) friend: FriendConfig,
+    #[serde(default)]
+    pub(crate) files: FileConfig,
+    #[serde(default)]
+    pub(crate) collage: CollageConfig,
+    #[serde(default)]
+    pub(crate) watermark: WatermarkConfig,
+    #[serde(default)]
+    pub(crate) fallback: FallbackConfig,
 }
@@ -0,0 +1,166 @@
+use std::{
+    error::Error,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat};
+use sha2::{Digest, Sha256};
+
+use super::{
+    structs::{TransformConfig, TransformStep},
+    write_atomic,
+};
+
+// 变换结果与原图放在一起，文件名中带上变换步骤的摘要，形如 `12345678_p0.original.1a2b3c4d.jpg`.
+// 摘要中也带上原图的修改时间和大小，原图被重新下载后不会用到旧的结果。
+fn variant_path(path: &Path, steps: &[TransformStep], ext: &str) -> PathBuf {
+    let source = fs::metadata(path)
+        .map(|meta| format!("{:?}{}", meta.modified().ok(), meta.len()))
+        .unwrap_or_default();
+    let digest = format!(
+        "{:x}",
+        Sha256::digest(format!("{:?}{}", steps, source).as_bytes())
+    );
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.{}", stem, &digest[..8], ext))
+}
+
+fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut buf = Vec::new();
+    if format == ImageFormat::Jpeg {
+        // jpeg 不支持透明通道。
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, quality))?;
+    } else {
+        image.write_to(&mut Cursor::new(&mut buf), format)?;
+    }
+    Ok(buf)
+}
+
+fn format_of(name: &str) -> Option<ImageFormat> {
+    ImageFormat::from_extension(name)
+}
+
+// 依次执行各个变换步骤，没有任何改动时返回 None.
+fn run(path: &Path, steps: &[TransformStep]) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let mut format = image::guess_format(&bytes)?;
+    if format == ImageFormat::Avif && !cfg!(feature = "avif") {
+        eprintln!(
+            "编译时没有启用 avif 特性，无法解码，跳过 {}",
+            path.display()
+        );
+        return Ok(None);
+    }
+    let mut image = image::load_from_memory_with_format(&bytes, format)?;
+    let mut modified = false;
+    let mut min_quality = 90;
+    let mut target_bytes = 0;
+    for step in steps {
+        match step {
+            TransformStep::Convert { from, to } => {
+                if from.iter().any(|from| format_of(from) == Some(format))
+                    && let Some(to) = format_of(to)
+                {
+                    format = to;
+                    modified = true;
+                }
+            }
+            TransformStep::Resize { max_dimension } => {
+                if image.width().max(image.height()) > *max_dimension {
+                    image = image.resize(*max_dimension, *max_dimension, FilterType::Lanczos3);
+                    modified = true;
+                }
+            }
+            // 重新编码时不会保留 EXIF 等元数据。
+            TransformStep::StripMetadata => modified = true,
+            TransformStep::Recompress {
+                target_bytes: target,
+                min_quality: quality,
+            } => {
+                if bytes.len() as u64 > *target || modified {
+                    target_bytes = *target;
+                    min_quality = (*quality).max(1);
+                    modified = true;
+                }
+            }
+        }
+    }
+    if !modified {
+        return Ok(None);
+    }
+    let mut buf = encode(&image, format, 90)?;
+    if target_bytes > 0 && buf.len() as u64 > target_bytes {
+        // 先降低 jpeg 的质量，还不够就缩小尺寸。
+        if format != ImageFormat::Jpeg {
+            format = ImageFormat::Jpeg;
+        }
+        let mut q = 90;
+        while buf.len() as u64 > target_bytes && q > min_quality {
+            q = q.saturating_sub(10).max(min_quality);
+            buf = encode(&image, format, q)?;
+        }
+        while buf.len() as u64 > target_bytes && image.width().max(image.height()) > 256 {
+            image = image.resize(
+                image.width() * 4 / 5,
+                image.height() * 4 / 5,
+                FilterType::Lanczos3,
+            );
+            buf = encode(&image, format, q)?;
+        }
+    }
+    let ext = format.extensions_str().first().unwrap_or(&"img");
+    let target = variant_path(path, steps, ext);
+    write_atomic(&target, |tmp| Ok(fs::write(tmp, buf)?))?;
+    Ok(Some(target))
+}
+
+// 返回实际应该上传的文件：变换后的结果，或者不需要变换、变换失败时的原图。
+// 变换结果会被缓存，同一张图片的同一组变换只计算一次；不需要变换时留下一个空的 `.noop` 文件作为记号。
+pub(crate) async fn prepare_upload(path: &Path, config: &TransformConfig) -> PathBuf {
+    if config.steps.is_empty() || fs::metadata(path).is_err() {
+        return path.to_path_buf();
+    }
+    let steps = config.steps.clone();
+    // 扩展名不确定，看看有没有算过的结果。
+    let probe = variant_path(path, &steps, "");
+    if let (Some(dir), Some(prefix)) = (probe.parent(), probe.file_name())
+        && let Ok(entries) = fs::read_dir(dir)
+    {
+        let prefix = prefix.to_string_lossy().to_string();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(&prefix) && name.ends_with(".noop") {
+                return path.to_path_buf();
+            }
+            if name.starts_with(&prefix) && !name.ends_with(".part") {
+                return entry.path();
+            }
+        }
+    }
+    let src = path.to_path_buf();
+    match tokio::task::spawn_blocking(move || run(&src, &steps).map_err(|err| err.to_string()))
+        .await
+    {
+        Ok(Ok(Some(target))) => target,
+        Ok(Ok(None)) => {
+            let _ = fs::write(variant_path(path, &config.steps, "noop"), "");
+            path.to_path_buf()
+        }
+        Ok(Err(err)) => {
+            eprintln!("无法变换 {}：{}", path.display(), err);
+            path.to_path_buf()
+        }
+        Err(err) => {
+            eprintln!("无法变换 {}：{}", path.display(), err);
+            path.to_path_buf()
+        }
+    }
+}
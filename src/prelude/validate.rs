@@ -0,0 +1,77 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use image::ImageReader;
+
+use super::{now, structs::ValidateConfig};
+
+// 检查下载的文件是否是完整的图片，以及尺寸是否与 api 给出的相符。
+// 403 之类的错误页面会被当作图片保存下来，截断的文件则会在上传时出错。
+fn check(
+    path: &Path,
+    size: &str,
+    (width, height): (i64, i64),
+    tolerance: f64,
+) -> Result<(), String> {
+    let mut head = [0; 64];
+    let len = fs::File::open(path)
+        .and_then(|mut file| file.read(&mut head))
+        .map_err(|err| err.to_string())?;
+    image::guess_format(&head[..len]).map_err(|_| "文件头不是已知的图片格式。".to_string())?;
+    let image = ImageReader::open(path)
+        .map_err(|err| err.to_string())?
+        .with_guessed_format()
+        .map_err(|err| err.to_string())?
+        .decode()
+        .map_err(|err| format!("无法解码：{}", err))?;
+    let (w, h) = (image.width() as f64, image.height() as f64);
+    if width <= 0 || height <= 0 {
+        return Ok(());
+    }
+    let (width, height) = (width as f64, height as f64);
+    match size {
+        "original" if w != width || h != height => {
+            Err(format!("尺寸为 {}x{}, 应为 {}x{}.", w, h, width, height))
+        }
+        // thumb 和 mini 是裁剪过的正方形，只检查保持比例的尺寸。
+        "regular" | "small" if ((w / h) / (width / height) - 1.0).abs() > tolerance => Err(
+            format!("宽高比为 {:.3}, 应为 {:.3}.", w / h, width / height),
+        ),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) async fn validate_image(
+    path: &Path,
+    size: &str,
+    dims: (i64, i64),
+    config: &ValidateConfig,
+) -> Result<(), String> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let path = path.to_path_buf();
+    let size = size.to_string();
+    let tolerance = config.tolerance;
+    tokio::task::spawn_blocking(move || check(&path, &size, dims, tolerance))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+// 将有问题的文件移到 `pictures/quarantine` 下，留待排查。
+pub(crate) fn quarantine(path: &Path) {
+    let mut dir = std::env::current_dir().unwrap();
+    dir.push("pictures");
+    dir.push("quarantine");
+    let _ = fs::create_dir_all(&dir);
+    let secs = now();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let target: PathBuf = dir.join(format!("{}.{}", secs, name));
+    if let Err(err) = fs::rename(path, &target) {
+        eprintln!("无法隔离 {}：{}", path.display(), err);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use ab_glyph::{Font, FontVec, PxScale};
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use sha2::{Digest, Sha256};
+
+use super::{
+    load_font,
+    structs::{WatermarkConfig, WatermarkPosition},
+    write_atomic,
+};
+
+// 字体中缺少署名中某些文字（通常是中文）的字形时只提示一次。
+static MISSING_GLYPHS: Once = Once::new();
+
+// 水印图与原图放在一起，文件名中带上署名、配置和原图的摘要，形如 `12345678_p0.original.wm1a2b3c4d.jpg`.
+fn watermark_path(path: &Path, text: &str, config: &WatermarkConfig) -> PathBuf {
+    let meta = fs::metadata(path).ok();
+    let modified = meta
+        .as_ref()
+        .and_then(|meta| meta.modified().ok())
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let key = format!(
+        "{}|{}|{}|{:?}|{}|{}|{}",
+        text,
+        config.font,
+        config.font_scale,
+        config.position,
+        config.opacity,
+        modified,
+        meta.map(|meta| meta.len()).unwrap_or_default()
+    );
+    let digest = format!("{:x}", Sha256::digest(key.as_bytes()));
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.wm{}.jpg", stem, &digest[..8]))
+}
+
+// 文字太长时从末尾截断，直到放得下。
+fn fit_text(text: &str, scale: PxScale, font: &FontVec, max_width: u32) -> String {
+    let mut chars = text.chars().collect::<Vec<_>>();
+    let mut fitted = text.to_string();
+    while !chars.is_empty() && text_size(scale, font, &fitted).0 > max_width {
+        chars.pop();
+        fitted = chars.iter().collect::<String>() + "…";
+    }
+    fitted
+}
+
+fn render(
+    src: &Path,
+    dst: &Path,
+    text: &str,
+    font: &FontVec,
+    config: &WatermarkConfig,
+) -> Result<(), String> {
+    let mut image = image::open(src).map_err(|err| err.to_string())?.to_rgb8();
+    let (width, height) = image.dimensions();
+    let size = (width.min(height) as f32 * config.font_scale).max(12.0);
+    let scale = PxScale::from(size);
+    let padding = (size / 3.0) as u32;
+    let text = fit_text(text, scale, font, width.saturating_sub(padding * 2));
+    let (text_w, text_h) = text_size(scale, font, &text);
+    let (box_w, box_h) = (
+        (text_w + padding * 2).min(width),
+        (text_h + padding * 2).min(height),
+    );
+    let (x, y) = match config.position {
+        WatermarkPosition::Footer | WatermarkPosition::BottomLeft => (0, height - box_h),
+        WatermarkPosition::TopLeft => (0, 0),
+        WatermarkPosition::TopRight => (width - box_w, 0),
+        WatermarkPosition::BottomRight => (width - box_w, height - box_h),
+    };
+    let box_w = match config.position {
+        WatermarkPosition::Footer => width,
+        _ => box_w,
+    };
+    // 先在副本上画出底色和文字，再按不透明度与原图混合。
+    let mut layer = RgbImage::from_pixel(box_w, box_h, Rgb([0, 0, 0]));
+    draw_text_mut(
+        &mut layer,
+        Rgb([255, 255, 255]),
+        padding as i32,
+        padding as i32,
+        scale,
+        font,
+        &text,
+    );
+    let alpha = config.opacity.clamp(0.0, 1.0);
+    for (dx, dy, over) in layer.enumerate_pixels() {
+        let pixel = image.get_pixel_mut(x + dx, y + dy);
+        for (c, o) in pixel.0.iter_mut().zip(over.0) {
+            *c = (*c as f32 * (1.0 - alpha) + o as f32 * alpha).round() as u8;
+        }
+    }
+    write_atomic(dst, |tmp| {
+        Ok(image.save_with_format(tmp, image::ImageFormat::Jpeg)?)
+    })
+    .map_err(|err| err.to_string())
+}
+
+// 返回画上署名的图片（已经画过的直接复用）。没有署名、没有启用或失败时返回原图。
+pub(crate) async fn apply_watermark(path: &Path, text: &str, config: &WatermarkConfig) -> PathBuf {
+    if !config.enabled || text.is_empty() || fs::metadata(path).is_err() {
+        return path.to_path_buf();
+    }
+    let font = load_font(&config.font);
+    if text
+        .chars()
+        .any(|c| !c.is_whitespace() && font.glyph_id(c).0 == 0)
+    {
+        MISSING_GLYPHS.call_once(|| {
+            eprintln!("水印字体中缺少部分文字的字形，请在 [watermark] 中配置支持中文的字体。")
+        });
+    }
+    let target = watermark_path(path, text, config);
+    if fs::metadata(&target).is_ok() {
+        return target;
+    }
+    let (src, dst) = (path.to_path_buf(), target.clone());
+    let (text, config) = (text.to_string(), config.clone());
+    match tokio::task::spawn_blocking(move || render(&src, &dst, &text, &font, &config)).await {
+        Ok(Ok(())) => target,
+        Ok(Err(err)) => {
+            eprintln!("无法给 {} 加水印：{}", path.display(), err);
+            path.to_path_buf()
+        }
+        Err(err) => {
+            eprintln!("无法给 {} 加水印：{}", path.display(), err);
+            path.to_path_buf()
+        }
+    }
+}